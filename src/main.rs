@@ -23,21 +23,28 @@
 
 use std::{
     borrow::Cow,
-    collections::{HashMap, VecDeque},
-    env, str,
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    net::{IpAddr, SocketAddr},
+    str,
     sync::{Arc, Mutex},
-    time::{Duration, SystemTime},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use tokio::{
     io::{AsyncReadExt as _, AsyncWriteExt as _},
     net::{TcpListener, TcpStream},
-    sync::oneshot::{Receiver, Sender},
-    sync::Mutex as TMutex,
+    sync::oneshot::Sender,
+    time as tokio_time,
 };
 
+mod pubsub;
+mod replication;
+mod shutdown;
+mod stats;
+
 /// Represent different types of possible values for a key.
-enum RedisType {
+pub(crate) enum RedisType {
     /// Array/list data type.
     List(VecDeque<String>),
     /// String data type.
@@ -45,39 +52,121 @@ enum RedisType {
 }
 
 /// Represent all the data for a key
-struct RedisValue {
+pub(crate) struct RedisValue {
     /// Creation time of the key
-    creation_time: SystemTime,
+    pub(crate) creation_time: SystemTime,
     /// Actual data
-    data: RedisType,
+    pub(crate) data: RedisType,
     /// TTL of the key
-    ttl: Option<u64>, // in ms; it is optional as it may not be present for every key and thus will be infinite
+    pub(crate) ttl: Option<u64>, // in ms; it is optional as it may not be present for every key and thus will be infinite
+}
+
+/// How often the active expiry cycle wakes up to sample the TTL set.
+const EXPIRY_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+/// Number of keys sampled from the TTL set per pass, mirroring Redis's default.
+const EXPIRY_SAMPLE_SIZE: usize = 20;
+/// Re-sample immediately within the same tick once more than this fraction of
+/// the sample came back expired, since that suggests many more keys are stale.
+const EXPIRY_RESAMPLE_THRESHOLD: f64 = 0.25;
+/// Upper bound on resample passes per tick, so a flood of expired keys can't
+/// make the active-expiry task hold the store mutex indefinitely.
+const EXPIRY_MAX_PASSES_PER_TICK: u32 = 16;
+
+/// A tiny linear-congruential generator, used only to pick random indices
+/// when sampling the TTL set; pulling in a full RNG crate for this would be
+/// overkill.
+struct SimpleRng(u64);
+
+impl SimpleRng {
+    /// Seed the generator from the current time.
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        Self(seed | 1)
+    }
+
+    /// Return a pseudo-random index in `0..bound`. Panics if `bound` is 0.
+    fn gen_range(&mut self, bound: usize) -> usize {
+        // Constants from Numerical Recipes.
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        // `u64 -> usize` never truncates on the 64-bit targets this server
+        // builds for, so there's no `cast_possible_truncation` to suppress here.
+        let word = (self.0 >> 33) as usize;
+        word % bound
+    }
 }
 
-// Periodically remove the expired keys
-// Procedure-
-// 1) Randomly sample the HashMap keys and check its TTL.
-// 2) If TTL is expired, then remove it from the HashMap. (TODO: need to do this efficiently, maybe by storing the keys in a Vec also)
-// 3) Sleep for some time.
-// 4) Repeat from step 1.
-// async fn delete_expired_keys(redis_key_val_store: Arc<Mutex<HashMap<String, RedisValue>>>) {
-//     loop {
-//         // Sleep for 1 second before checking for expired keys
-//         time::sleep(Duration::from_secs(1)).await;
-//         let mut store = redis_key_val_store.lock().unwrap();
-//         let current_time = SystemTime::now();
-//         // let dbsize = store.len();
-//
-//         // Iterate through the keys and remove expired ones
-//         store.retain(|_, value| {
-//             if let Some(ttl) = value.ttl {
-//                 current_time < value.creation_time + Duration::from_millis(ttl)
-//             } else {
-//                 true // Keep keys with no TTL
-//             }
-//         });
-//     }
-// }
+/// Periodically remove expired keys using Redis-style adaptive sampling.
+///
+/// Rather than scanning the whole keyspace, each pass draws up to
+/// `EXPIRY_SAMPLE_SIZE` keys from `ttl_keys` (a side set tracking only keys
+/// that carry a TTL) and deletes the ones that have expired. If more than
+/// `EXPIRY_RESAMPLE_THRESHOLD` of the sample was expired, another pass runs
+/// immediately (capped by `EXPIRY_MAX_PASSES_PER_TICK`); otherwise the task
+/// sleeps until the next tick. Passive expiry in `get` remains as a fallback
+/// for keys this sampling hasn't caught up with yet.
+async fn delete_expired_keys(
+    redis_key_val_store: Arc<Mutex<HashMap<String, RedisValue>>>,
+    ttl_keys: Arc<Mutex<HashSet<String>>>,
+    stats: stats::SharedStats,
+) {
+    let mut rng = SimpleRng::new();
+
+    loop {
+        tokio_time::sleep(EXPIRY_CYCLE_INTERVAL).await;
+
+        for _ in 0..EXPIRY_MAX_PASSES_PER_TICK {
+            let mut sample_keys: Vec<String> = {
+                let keys = ttl_keys.lock().unwrap();
+                keys.iter().cloned().collect()
+            };
+            if sample_keys.is_empty() {
+                break;
+            }
+            let sample_size = EXPIRY_SAMPLE_SIZE.min(sample_keys.len());
+            let mut sample = Vec::with_capacity(sample_size);
+            for _ in 0..sample_size {
+                let ind = rng.gen_range(sample_keys.len());
+                sample.push(sample_keys.swap_remove(ind));
+            }
+
+            let current_time = SystemTime::now();
+            let mut store = redis_key_val_store.lock().unwrap();
+            let mut ttl_keys_guard = ttl_keys.lock().unwrap();
+            let mut expired_count = 0;
+            for key in &sample {
+                let is_expired = store.get(key).is_some_and(|value| {
+                    value
+                        .ttl
+                        .is_some_and(|ttl| current_time > value.creation_time + Duration::from_millis(ttl))
+                });
+                if is_expired {
+                    store.remove(key);
+                    expired_count += 1;
+                    stats.record_expired();
+                }
+                if is_expired || !store.contains_key(key) {
+                    // Either just expired, or removed through some other path
+                    // (e.g. passive expiry in `get`): stop tracking its TTL.
+                    ttl_keys_guard.remove(key);
+                }
+            }
+            drop(store);
+            drop(ttl_keys_guard);
+
+            #[expect(clippy::cast_precision_loss, reason = "Sample size is always small")]
+            let expired_fraction = expired_count as f64 / sample.len() as f64;
+            if expired_fraction <= EXPIRY_RESAMPLE_THRESHOLD {
+                break;
+            }
+        }
+    }
+}
 
 /// Compute output of the LRANGE command in human readable form, or an error
 fn lrange(
@@ -126,385 +215,815 @@ fn lrange(
     Ok(output_array)
 }
 
-/// A very basic parser for RESP
-/// Currently only handles non-nested arrays
-/// Returns the parsed output in human readable form
-fn parse_command(input: &str) -> Vec<String> {
-    #[expect(
-        clippy::collection_is_never_read,
-        reason = "Will solve this in future when writing proper parser"
-    )]
-    let mut command_list: Vec<String> = Vec::new();
-
-    let mut input_it = input.trim().chars().enumerate();
-    // for (ind, char) in input_it {
-    while let Some((ind, char)) = input_it.next() {
-        #[expect(
-            clippy::single_match,
-            reason = "Will solve this in future when writing proper parser"
-        )]
-        match char {
-            '*' => {
-                let (ind2, _) = input_it.find(|&x| x.1 == '\r').unwrap();
-                command_list.push(String::from(&input[ind..ind2]));
-                input_it.next();
+/// Number of bytes read from the socket per `read()` call.
+pub(crate) const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Upper bound on a `*<n>\r\n` array header's declared element count,
+/// matching real Redis's default `proto-max-multibulk-len`. Declared counts
+/// are attacker-controlled and read before any of the elements have arrived,
+/// so they must be sanity-checked before being used to size an allocation.
+const MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
+
+/// Upper bound on a `$<len>\r\n` bulk string header's declared byte length,
+/// matching real Redis's default `proto-max-bulk-len`.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Outcome of attempting to parse one RESP frame from the front of a buffer.
+pub(crate) enum ParsedFrame {
+    /// `buf` does not yet hold a complete frame; read more bytes and retry.
+    Incomplete,
+    /// A complete frame was parsed.
+    Complete {
+        /// The command and its arguments, as raw bytes (binary-safe).
+        args: Vec<Vec<u8>>,
+        /// Number of bytes from the front of the buffer this frame consumed.
+        consumed: usize,
+    },
+    /// The frame's header declared an array count or bulk length outside
+    /// the sane bounds above; the connection should be closed rather than
+    /// trusted further.
+    Invalid,
+}
+
+/// Find the index of the `\r` starting the next "\r\n" in `buf[from..]`, relative to `buf`.
+fn find_crlf(buf: &[u8], from: usize) -> Option<usize> {
+    buf[from..]
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|ind| ind + from)
+}
+
+/// A binary-safe RESP parser driven by length prefixes.
+///
+/// Parses one `*<n>\r\n` array of `$<len>\r\n<len bytes>\r\n` bulk strings from
+/// the front of `buf`, copying the declared number of bytes verbatim rather
+/// than scanning for delimiters, so values containing `\r\n` (or starting
+/// with `*`/`$`) round-trip correctly. Also accepts a bare inline command
+/// line, the way `redis-cli` sometimes sends one. Returns
+/// `ParsedFrame::Incomplete` rather than panicking when `buf` doesn't yet
+/// hold a full frame.
+pub(crate) fn parse_command(buf: &[u8]) -> ParsedFrame {
+    if buf.is_empty() {
+        return ParsedFrame::Incomplete;
+    }
+
+    if buf[0] == b'*' {
+        let Some(header_end) = find_crlf(buf, 0) else {
+            return ParsedFrame::Incomplete;
+        };
+        let Ok(count) = str::from_utf8(&buf[1..header_end])
+            .unwrap_or_default()
+            .parse::<i64>()
+        else {
+            return ParsedFrame::Incomplete;
+        };
+        if count > MAX_MULTIBULK_LEN {
+            return ParsedFrame::Invalid;
+        }
+
+        let mut pos = header_end + 2;
+        let mut args = Vec::with_capacity(count.max(0).unsigned_abs() as usize);
+        for _ in 0..count.max(0) {
+            if buf.get(pos) != Some(&b'$') {
+                return ParsedFrame::Incomplete;
+            }
+            let Some(len_end) = find_crlf(buf, pos) else {
+                return ParsedFrame::Incomplete;
+            };
+            let Ok(len) = str::from_utf8(&buf[pos + 1..len_end])
+                .unwrap_or_default()
+                .parse::<usize>()
+            else {
+                return ParsedFrame::Incomplete;
+            };
+            if len > MAX_BULK_LEN {
+                return ParsedFrame::Invalid;
             }
-            _ => (),
+
+            let data_start = len_end + 2;
+            let data_end = data_start + len;
+            if buf.len() < data_end + 2 {
+                return ParsedFrame::Incomplete;
+            }
+            args.push(buf[data_start..data_end].to_vec());
+            pos = data_end + 2;
+        }
+        ParsedFrame::Complete { args, consumed: pos }
+    } else {
+        // Inline command: a single line terminated by "\n", space-separated.
+        let Some(line_end) = buf.iter().position(|&b| b == b'\n') else {
+            return ParsedFrame::Incomplete;
+        };
+        let line = buf[..line_end].strip_suffix(b"\r").unwrap_or(&buf[..line_end]);
+        let args = line
+            .split(|&b| b == b' ')
+            .filter(|part| !part.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        ParsedFrame::Complete {
+            args,
+            consumed: line_end + 1,
         }
     }
+}
 
-    // Currently, always guaranteed to contain a value
-    // let _vector_length = command_list.pop().unwrap();
+/// A single blocked client's wakeup handle. `BLPOP`/`BRPOP` register the same
+/// waiter under every key they watch; whichever key is pushed to first takes
+/// the `Sender` out and wakes the client, leaving the other keys' queues
+/// holding an already-empty slot that gets skipped (and dropped) the next
+/// time something is pushed to them.
+pub(crate) type Waiter = Arc<Mutex<Option<Sender<()>>>>;
 
-    // Extract only the valid strings for now
-    // The previous code is useless for now, but, might become useful later on
-    let mut command_list: Vec<String> = vec![];
-    for string in input.lines() {
-        if string.starts_with(['*', '$']) {
-            continue;
+/// Wake the single oldest still-live waiter registered on `key`, if any,
+/// discarding any stale entries left behind by a waiter that was already
+/// woken via a different key it was also watching, or whose client has
+/// stopped waiting (its receiver is gone).
+pub(crate) fn wake_one_waiter(oneshot_store: &Mutex<HashMap<String, VecDeque<Waiter>>>, key: &str) {
+    if let Some(waiters) = oneshot_store.lock().unwrap().get_mut(key) {
+        while let Some(waiter) = waiters.pop_front() {
+            if let Some(sender) = waiter.lock().unwrap().take() {
+                if sender.send(()).is_ok() {
+                    break;
+                }
+            }
         }
-        command_list.push(String::from(string));
     }
+}
+
+/// Shared implementation behind `BLPOP`/`BRPOP`: watch `keys` (checked in the
+/// order given) for an element, popping the front (`pop_front = true`) or
+/// back of the first one that has one. Blocks and registers interest on
+/// every watched key when none do, re-checking all of them on every wakeup
+/// (to absorb spurious wakeups and lost races against other blocked
+/// clients), until `timeout_secs` elapses (`None` or `0` blocks forever).
+async fn blocking_list_pop(
+    redis_key_val_store: &Arc<Mutex<HashMap<String, RedisValue>>>,
+    oneshot_store: &Arc<Mutex<HashMap<String, VecDeque<Waiter>>>>,
+    replication: &replication::SharedReplication,
+    keys: &[String],
+    timeout_secs: Option<f64>,
+    pop_front: bool,
+) -> String {
+    let deadline = timeout_secs
+        .filter(|&secs| secs > 0.0)
+        .map(|secs| tokio_time::Instant::now() + Duration::from_secs_f64(secs));
 
-    command_list
+    loop {
+        // Register interest on every watched key *before* checking the
+        // store, so a push landing between the check and the wait can never
+        // be missed. If we end up popping something ourselves below, these
+        // registrations are simply left as stale entries, exactly like the
+        // ones `wake_one_waiter` already discards when a waiter watching
+        // multiple keys gets woken via one of the others.
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let waiter: Waiter = Arc::new(Mutex::new(Some(tx)));
+        {
+            let mut oneshot_guard = oneshot_store.lock().unwrap();
+            for key in keys {
+                oneshot_guard
+                    .entry(key.clone())
+                    .or_default()
+                    .push_back(Arc::clone(&waiter));
+            }
+        }
+
+        let pop_result: Option<Result<(String, String), ()>> = {
+            let mut store = redis_key_val_store.lock().unwrap();
+            let mut result = None;
+            for key in keys {
+                if let Some(redis_val) = store.get_mut(key) {
+                    #[expect(
+                        clippy::match_wildcard_for_single_variants,
+                        reason = "BLPOP/BRPOP only work on List"
+                    )]
+                    match redis_val.data {
+                        RedisType::List(ref mut list) => {
+                            let popped = if pop_front { list.pop_front() } else { list.pop_back() };
+                            if let Some(val) = popped {
+                                // Remove the key from the store if its list has become empty
+                                if list.is_empty() {
+                                    store.remove(key);
+                                }
+                                result = Some(Ok((key.clone(), val)));
+                                break;
+                            }
+                        }
+                        _ => {
+                            result = Some(Err(()));
+                            break;
+                        }
+                    }
+                }
+            }
+            result
+        };
+
+        if let Some(outcome) = pop_result {
+            return match outcome {
+                Ok((key, val)) => {
+                    // Propagate the equivalent non-blocking pop so a
+                    // replica's copy of the list stays in sync with what
+                    // the master actually removed.
+                    let pop_command_name = if pop_front { "LPOP" } else { "RPOP" };
+                    replication.propagate(&[pop_command_name.to_owned(), key.clone()]);
+                    format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, val.len(), val)
+                }
+                Err(()) => "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_owned(),
+            };
+        }
+
+        let timed_out = match deadline {
+            Some(deadline) => tokio_time::timeout_at(deadline, rx).await.is_err(),
+            None => {
+                // A sender being dropped without sending (which shouldn't
+                // normally happen) is treated the same as a spurious wakeup:
+                // loop and re-check.
+                let _ = rx.await;
+                false
+            }
+        };
+        if timed_out {
+            // RESP null array: no element arrived in time.
+            return "*-1\r\n".to_owned();
+        }
+        // Woken up: loop back and re-check every watched key rather than
+        // assuming data is there, since another waiter may have won the
+        // race for it.
+    }
 }
 
 #[expect(
     clippy::too_many_lines,
     reason = "Will handle this later by creating a Redis class"
 )]
+#[expect(
+    clippy::too_many_arguments,
+    reason = "Each argument is a distinct piece of shared connection state; bundling them into a struct is follow-up work for the same Redis class mentioned above"
+)]
 /// Process a client connection
 /// This function handles multiple requests from a single client
 async fn process(
     mut stream: TcpStream,
     redis_key_val_store: Arc<Mutex<HashMap<String, RedisValue>>>,
     // oneshot_store: Arc<TMutex<HashMap<String, VecDeque<Sender<()>>>>>,
-    oneshot_store: Arc<Mutex<HashMap<String, VecDeque<Sender<()>>>>>,
+    oneshot_store: Arc<Mutex<HashMap<String, VecDeque<Waiter>>>>,
+    ttl_keys_store: Arc<Mutex<HashSet<String>>>,
+    pubsub_broker: pubsub::Broker,
+    stats: stats::SharedStats,
+    replication: replication::SharedReplication,
+    shutdown: shutdown::SharedShutdown,
+    my_port: u16,
 ) {
-    // Can handle input string of 1024 bytes
-    let mut buf = [0; 1024];
+    stats.record_connection();
+
+    // Growable buffer holding bytes read but not yet parsed into a command.
+    // `filled` tracks how many bytes at the front of `buf` are valid data;
+    // the buffer only grows when a single frame does not fit in it yet.
+    let mut buf = vec![0_u8; READ_CHUNK_SIZE];
+    let mut filled = 0;
 
-    while let Ok(bytes_read) = stream.read(&mut buf).await {
+    loop {
+        if filled == buf.len() {
+            buf.resize(filled + READ_CHUNK_SIZE, 0);
+        }
+
+        let read_end = buf.len().min(filled + READ_CHUNK_SIZE);
+        let bytes_read = match stream.read(&mut buf[filled..read_end]).await {
+            Ok(bytes_read) => bytes_read,
+            Err(_) => break,
+        };
         if bytes_read == 0 {
             break;
         }
-        let parsed_command = parse_command(str::from_utf8(&buf[..bytes_read]).unwrap());
-
-        // Main Redis Server functioning
-
-        // Redis commands are case insensitive
-        let redis_output = match parsed_command[0].to_lowercase().as_str() {
-            "ping" => "+PONG\r\n",
-            "echo" => &format!("+{}\r\n", parsed_command[1].as_str()), // TODO: why .as_str() doesn't work here???
-            "client" => "+OK\r\n+OK\r\n", // Default redis-rs client sends 2 arrays when creating connection
-            "set" => {
-                // If expiry time parameters are passed then set TTL (in ms) else TTL is infinite
-                let ttl_ms = if parsed_command.len() >= 5 {
-                    Some(parsed_command[4].parse::<u64>().unwrap())
-                } else {
-                    None // Infinite TTL
-                };
+        filled += bytes_read;
 
-                // This overwrites the value if the key already exists
-                redis_key_val_store.lock().unwrap().insert(
-                    parsed_command[1].clone(),
-                    RedisValue {
-                        data: RedisType::Val(parsed_command[2].clone()),
-                        creation_time: SystemTime::now(),
-                        ttl: ttl_ms,
-                    },
-                );
-                "+OK\r\n"
+        // Drain every complete frame currently buffered before reading again,
+        // coalescing all of their replies into one write so a pipelined batch
+        // of commands costs a single syscall instead of one per command.
+        let mut consumed = 0;
+        let mut pipelined_output = Vec::new();
+        loop {
+            let (args, frame_len) = match parse_command(&buf[consumed..filled]) {
+                ParsedFrame::Incomplete => break,
+                ParsedFrame::Complete { args, consumed } => (args, consumed),
+                ParsedFrame::Invalid => {
+                    pipelined_output
+                        .extend_from_slice(b"-ERR Protocol error: invalid multibulk length\r\n");
+                    let _ = stream.write_all(&pipelined_output).await;
+                    return;
+                }
+            };
+            consumed += frame_len;
+            if args.is_empty() {
+                continue;
             }
-            "get" => {
-                let mut store = redis_key_val_store.lock().unwrap();
-                #[expect(
-                    clippy::option_if_let_else,
-                    reason = "Difficult to handle this case as `store` is causing borrow checker issues inside closure"
-                )]
-                let returned_value = match store.get(parsed_command[1].as_str()) {
-                    Some(x) => {
-                        let key_expired = x.ttl.is_some()
-                            && SystemTime::now()
-                                > x.creation_time + Duration::from_millis(x.ttl.unwrap());
-                        if key_expired {
-                            // Remove the key as it has expired
-                            // This is called "PASSIVE EXPIRY" in Redis
-                            store.remove(&parsed_command[1]);
-                            drop(store);
-                            "$-1" // Return "Null bulk string" if the input key has expired and consequently does not exist
-                        } else {
-                            #[expect(clippy::match_wildcard_for_single_variants, reason="As only RedisType::Val is allowed for 'GET' operations")]
-                            match x.data {
-                                // Only accept string values
-                                RedisType::Val(ref val) => &format!("${}\r\n{}", val.len(), val),
-                                _ => "-WRONGTYPE Operation against a key holding the wrong kind of value",
-                            }
-                        }
-                    }
-                    None => "$-1", // Return "Null bulk string" if the input key does not exist
+            // The rest of the dispatch below still works on UTF-8 text; lossily
+            // convert here until the command handlers are made binary-safe.
+            let parsed_command: Vec<String> = args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            stats.record_command(&parsed_command[0].to_lowercase());
+
+            // `SUBSCRIBE`/`PSUBSCRIBE` hand the connection off to the
+            // pub/sub subsystem for the rest of its lifetime, so it needs to
+            // take `stream` before the normal per-command dispatch below runs.
+            let command_lower = parsed_command[0].to_lowercase();
+            if command_lower == "subscribe" || command_lower == "psubscribe" {
+                if !pipelined_output.is_empty() && stream.write_all(&pipelined_output).await.is_err() {
+                    return;
+                }
+                let (channels, patterns) = if command_lower == "subscribe" {
+                    (parsed_command[1..].to_vec(), Vec::new())
+                } else {
+                    (Vec::new(), parsed_command[1..].to_vec())
                 };
-                &format!("{returned_value}\r\n")
+                // Anything already read past this frame (e.g. a pipelined
+                // `PING` sent right after `SUBSCRIBE` in the same segment)
+                // belongs to the pub/sub subsystem now, not us.
+                let leftover = buf[consumed..filled].to_vec();
+                pubsub::run(stream, pubsub_broker, channels, patterns, leftover).await;
+                return;
             }
-            // In milliseconds
-            "ttl" => {
-                let returned_value = redis_key_val_store
-                    .lock()
-                    .unwrap()
-                    .get(parsed_command[1].as_str())
-                    .map_or_else(
-                        || String::from("-1"), // Key does not exist
-                        |x| {
-                            x.ttl.map_or_else(
-                                || String::from("-1"), // Key with no TTL set
-                                |ttl| {
-                                    let ttl_left = (x.creation_time + Duration::from_millis(ttl))
-                                        .duration_since(SystemTime::now())
-                                        .unwrap_or_default(); // When the key has expired, set the duration to default of 0
-                                    ttl_left.as_millis().to_string()
-                                },
-                            )
-                        },
-                    );
-                &format!("+{returned_value}\r\n")
+
+            // `PSYNC` hands the connection off to the replication subsystem
+            // for the rest of its lifetime, exactly like `SUBSCRIBE` does
+            // for pub/sub.
+            if command_lower == "psync" {
+                if !pipelined_output.is_empty() && stream.write_all(&pipelined_output).await.is_err() {
+                    return;
+                }
+                let leftover = buf[consumed..filled].to_vec();
+                replication::handle_psync(stream, replication, leftover).await;
+                return;
             }
-            "dbsize" => {
-                let dbsize = redis_key_val_store.lock().unwrap().len();
-                &format!(":{dbsize}\r\n")
+
+            // Real Redis closes the connection rather than replying, since
+            // the server is about to go away; `NOSAVE` is accepted but
+            // makes no difference here, as this server has no persistence
+            // to skip in the first place.
+            if command_lower == "shutdown" {
+                shutdown.trigger();
+                return;
             }
-            "rpush" => {
-                let mut store = redis_key_val_store.lock().unwrap();
-
-                // Get the reference to the value; if the key doesn't exist then create it
-                let redis_val =
-                    store
-                        .entry(parsed_command[1].clone())
-                        .or_insert_with(|| RedisValue {
-                            data: RedisType::List(VecDeque::new()),
-                            creation_time: SystemTime::now(),
-                            ttl: None,
-                        });
 
-                #[expect(
-                    clippy::match_wildcard_for_single_variants,
-                    reason = "rpush command works only on List"
-                )]
-                // Insert the desired data to the referenced value, taking care of errors
-                let insertion_result: Result<usize, &str> = match redis_val.data {
-                    RedisType::List(ref mut list) => {
-                        if parsed_command.len() <= 2 {
-                            Err("ERR wrong number of arguments for command")
-                        } else {
-                            // // for x in parsed_command[2..].to_vec() {  TODO: why not ???
-                            // for x in parsed_command[2..].iter().cloned() {
-                            //     list.push_back(x);
-                            // }
-                            // TODO: Time this with above 3 lines
-                            // list.append(&mut parsed_command[2..].to_vec().into());
-                            // TODO: Time this with above line
-                            list.extend(parsed_command[2..].iter().cloned());
-                            Ok(list.len())
-                        }
-                    }
-                    _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                };
-                drop(store);
+            // Main Redis Server functioning
 
-                // Convert to RESP and return the result
-                match insertion_result {
-                    Ok(len) => &format!(":{len}\r\n"),
-                    Err(err) => &format!("-{err}\r\n"),
-                }
-            }
-            "lpush" => {
-                let mut store = redis_key_val_store.lock().unwrap();
-
-                // Get the reference to the value; if the key doesn't exist then create it
-                let redis_val =
-                    store
-                        .entry(parsed_command[1].clone())
-                        .or_insert_with(|| RedisValue {
-                            data: RedisType::List(VecDeque::new()),
+            // Redis commands are case insensitive
+            let redis_output = match parsed_command[0].to_lowercase().as_str() {
+                "ping" => "+PONG\r\n",
+                "echo" => &format!("+{}\r\n", parsed_command[1].as_str()), // TODO: why .as_str() doesn't work here???
+                "client" => "+OK\r\n", // e.g. `CLIENT SETINFO LIB-NAME ...`/`LIB-VER ...`, sent as separate frames
+                "set" => {
+                    // If expiry time parameters are passed then set TTL (in ms) else TTL is infinite
+                    let ttl_ms = if parsed_command.len() >= 5 {
+                        Some(parsed_command[4].parse::<u64>().unwrap())
+                    } else {
+                        None // Infinite TTL
+                    };
+
+                    // This overwrites the value if the key already exists
+                    redis_key_val_store.lock().unwrap().insert(
+                        parsed_command[1].clone(),
+                        RedisValue {
+                            data: RedisType::Val(parsed_command[2].clone()),
                             creation_time: SystemTime::now(),
-                            ttl: None,
-                        });
+                            ttl: ttl_ms,
+                        },
+                    );
 
-                #[expect(
-                    clippy::match_wildcard_for_single_variants,
-                    reason = "lpush command works only on List"
-                )]
-                // Insert the desired data to the referenced value, taking care of errors
-                let insertion_result: Result<usize, &str> = match redis_val.data {
-                    RedisType::List(ref mut list) => {
-                        if parsed_command.len() <= 2 {
-                            Err("ERR wrong number of arguments for command")
-                        } else {
-                            for x in parsed_command[2..].iter().cloned() {
-                                list.push_front(x);
-                            }
+                    // Keep the TTL side set (used by active expiry sampling)
+                    // in sync: track the key while it carries a TTL, and stop
+                    // once it doesn't.
+                    let mut ttl_keys = ttl_keys_store.lock().unwrap();
+                    if ttl_ms.is_some() {
+                        ttl_keys.insert(parsed_command[1].clone());
+                    } else {
+                        ttl_keys.remove(&parsed_command[1]);
+                    }
+                    drop(ttl_keys);
 
-                            // Send trigger to the channel for the specified list
-                            // See `lbpop`
-                            // let _a = oneshot_store.lock().unwrap();
-                            if let Some(key) =
-                                oneshot_store.lock().unwrap().get_mut(&parsed_command[1])
-                            {
-                                if let Some(sender) = key.pop_front() {
-                                    sender.send(()).unwrap();
+                    replication.propagate(&parsed_command);
+                    "+OK\r\n"
+                }
+                "get" => {
+                    let mut store = redis_key_val_store.lock().unwrap();
+                    #[expect(
+                        clippy::option_if_let_else,
+                        reason = "Difficult to handle this case as `store` is causing borrow checker issues inside closure"
+                    )]
+                    let returned_value = match store.get(parsed_command[1].as_str()) {
+                        Some(x) => {
+                            let key_expired = x.ttl.is_some()
+                                && SystemTime::now()
+                                    > x.creation_time + Duration::from_millis(x.ttl.unwrap());
+                            if key_expired {
+                                // Remove the key as it has expired
+                                // This is called "PASSIVE EXPIRY" in Redis
+                                store.remove(&parsed_command[1]);
+                                drop(store);
+                                ttl_keys_store.lock().unwrap().remove(&parsed_command[1]);
+                                stats.record_expired();
+                                stats.record_miss();
+                                "$-1" // Return "Null bulk string" if the input key has expired and consequently does not exist
+                            } else {
+                                #[expect(clippy::match_wildcard_for_single_variants, reason="As only RedisType::Val is allowed for 'GET' operations")]
+                                match x.data {
+                                    // Only accept string values
+                                    RedisType::Val(ref val) => {
+                                        stats.record_hit();
+                                        &format!("${}\r\n{}", val.len(), val)
+                                    }
+                                    _ => "-WRONGTYPE Operation against a key holding the wrong kind of value",
                                 }
                             }
-
-                            Ok(list.len())
                         }
+                        None => {
+                            stats.record_miss();
+                            "$-1" // Return "Null bulk string" if the input key does not exist
+                        }
+                    };
+                    &format!("{returned_value}\r\n")
+                }
+                // In milliseconds
+                "ttl" => {
+                    let returned_value = redis_key_val_store
+                        .lock()
+                        .unwrap()
+                        .get(parsed_command[1].as_str())
+                        .map_or_else(
+                            || String::from("-1"), // Key does not exist
+                            |x| {
+                                x.ttl.map_or_else(
+                                    || String::from("-1"), // Key with no TTL set
+                                    |ttl| {
+                                        let ttl_left = (x.creation_time + Duration::from_millis(ttl))
+                                            .duration_since(SystemTime::now())
+                                            .unwrap_or_default(); // When the key has expired, set the duration to default of 0
+                                        ttl_left.as_millis().to_string()
+                                    },
+                                )
+                            },
+                        );
+                    &format!("+{returned_value}\r\n")
+                }
+                "dbsize" => {
+                    let dbsize = redis_key_val_store.lock().unwrap().len();
+                    &format!(":{dbsize}\r\n")
+                }
+                "info" => {
+                    let dbsize = redis_key_val_store.lock().unwrap().len();
+                    let expires = ttl_keys_store.lock().unwrap().len();
+                    let section = parsed_command.get(1).map_or("", String::as_str);
+                    let mut info = stats::render(&stats, dbsize, expires, section);
+                    if section.is_empty() || section.eq_ignore_ascii_case("replication") {
+                        info.push_str(&replication.render_info());
                     }
-                    _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value"),
-                };
-                drop(store);
-
-                // Convert to RESP and return the result
-                match insertion_result {
-                    Ok(len) => &format!(":{len}\r\n"),
-                    Err(err) => &format!("-{err}\r\n"),
+                    &format!("${}\r\n{info}\r\n", info.len())
                 }
-            }
-            "lrange" => {
-                let lrange_output = lrange(&redis_key_val_store, &parsed_command);
-
-                // Convert to RESP and return the result
-                match lrange_output {
-                    Ok(output_array) => {
-                        let output_string = format!("*{}\r\n", output_array.len());
-                        &output_array.iter().fold(output_string.clone(), |acc, x| {
-                            acc + "$" + x.len().to_string().as_str() + "\r\n" + x + "\r\n"
-                        })
+                "replconf" => "+OK\r\n",
+                "wait" => {
+                    let numreplicas = parsed_command[1].parse::<usize>().unwrap_or(0);
+                    let timeout_ms = parsed_command[2].parse::<u64>().unwrap_or(0);
+                    let acked = replication.wait_for_acks(numreplicas, timeout_ms).await;
+                    &format!(":{acked}\r\n")
+                }
+                "replicaof" => {
+                    if parsed_command[1].eq_ignore_ascii_case("no")
+                        && parsed_command.get(2).is_some_and(|arg| arg.eq_ignore_ascii_case("one"))
+                    {
+                        replication.become_master();
+                    } else if let Ok(port) = parsed_command[2].parse::<u16>() {
+                        let host = parsed_command[1].clone();
+                        replication.become_replica(host.clone(), port);
+                        tokio::spawn(replication::run_replica(
+                            host,
+                            port,
+                            my_port,
+                            Arc::clone(&redis_key_val_store),
+                            Arc::clone(&ttl_keys_store),
+                            Arc::clone(&oneshot_store),
+                        ));
                     }
-                    Err(err) => &format!("-{err}\r\n"),
+                    "+OK\r\n"
                 }
-            }
-            "llen" => {
-                let store = redis_key_val_store.lock().unwrap();
-
-                #[expect(clippy::match_wildcard_for_single_variants, reason = "llen command works only on List")]
-                &store
-                    .get(parsed_command[1].as_str())
-                    .map_or(Cow::Borrowed(":0\r\n"), |redis_val| match redis_val.data {
-                        RedisType::List(ref list) => Cow::Owned(format!(":{}\r\n", list.len())),
-                        _ => {
-                            Cow::Borrowed("-WRONGTYPE Operation against a key holding the wrong kind of value\r\n")
-                        }
-                    })
-            }
-            "lpop" => {
-                let mut store = redis_key_val_store.lock().unwrap();
-                let times_to_pop = parsed_command
-                    .get(2)
-                    .map_or(1, |x| x.parse::<u32>().unwrap());
+                "publish" => {
+                    let receiver_count =
+                        pubsub::publish(&pubsub_broker, &parsed_command[1], &parsed_command[2]).await;
+                    &format!(":{receiver_count}\r\n")
+                }
+                // A client that was never in subscriber mode issuing `UNSUBSCRIBE`
+                // has nothing to unsubscribe from; reply with a nil channel name
+                // and a subscription count of 0, as real Redis does.
+                "unsubscribe" => "*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n",
+                "rpush" => {
+                    let mut store = redis_key_val_store.lock().unwrap();
+
+                    // Get the reference to the value; if the key doesn't exist then create it
+                    let redis_val =
+                        store
+                            .entry(parsed_command[1].clone())
+                            .or_insert_with(|| RedisValue {
+                                data: RedisType::List(VecDeque::new()),
+                                creation_time: SystemTime::now(),
+                                ttl: None,
+                            });
 
-                if let Some(redis_val) = store.get_mut(parsed_command[1].as_str()) {
                     #[expect(
                         clippy::match_wildcard_for_single_variants,
-                        reason = "lpop command works only on List"
+                        reason = "rpush command works only on List"
                     )]
-                    match redis_val.data {
+                    // Insert the desired data to the referenced value, taking care of errors
+                    let insertion_result: Result<usize, &str> = match redis_val.data {
                         RedisType::List(ref mut list) => {
-                            let output_array: Vec<String> =
-                                (1..=times_to_pop).map_while(|_| list.pop_front()).collect();
+                            if parsed_command.len() <= 2 {
+                                Err("ERR wrong number of arguments for command")
+                            } else {
+                                // // for x in parsed_command[2..].to_vec() {  TODO: why not ???
+                                // for x in parsed_command[2..].iter().cloned() {
+                                //     list.push_back(x);
+                                // }
+                                // TODO: Time this with above 3 lines
+                                // list.append(&mut parsed_command[2..].to_vec().into());
+                                // TODO: Time this with above line
+                                list.extend(parsed_command[2..].iter().cloned());
 
-                            // Remove the key from the store if its list has become empty
-                            if list.is_empty() {
-                                store.remove(&parsed_command[1]);
-                            }
-                            drop(store);
+                                // Wake exactly one blocked `BLPOP`/`BRPOP` waiter, if any.
+                                // See `wake_one_waiter`
+                                wake_one_waiter(&oneshot_store, &parsed_command[1]);
 
-                            if output_array.is_empty() {
-                                "$-1\r\n"
-                            } else if output_array.len() == 1 {
-                                &format!("${}\r\n{}\r\n", output_array[0].len(), output_array[0])
-                            } else {
-                                let output_string = format!("*{}\r\n", output_array.len());
-                                &output_array.iter().fold(output_string.clone(), |acc, x| {
-                                    acc + "$" + x.len().to_string().as_str() + "\r\n" + x + "\r\n"
-                                })
+                                Ok(list.len())
                             }
                         }
-                        _ => {
-                            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
-                        }
+                        _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    };
+                    drop(store);
+
+                    if insertion_result.is_ok() {
+                        replication.propagate(&parsed_command);
+                    }
+
+                    // Convert to RESP and return the result
+                    match insertion_result {
+                        Ok(len) => &format!(":{len}\r\n"),
+                        Err(err) => &format!("-{err}\r\n"),
                     }
-                } else {
-                    "$-1\r\n"
                 }
-            }
-            "lbpop" => {
-                let db_list_name = &parsed_command[1];
-                // let mut store = redis_key_val_store.lock().unwrap();
+                "lpush" => {
+                    let mut store = redis_key_val_store.lock().unwrap();
+
+                    // Get the reference to the value; if the key doesn't exist then create it
+                    let redis_val =
+                        store
+                            .entry(parsed_command[1].clone())
+                            .or_insert_with(|| RedisValue {
+                                data: RedisType::List(VecDeque::new()),
+                                creation_time: SystemTime::now(),
+                                ttl: None,
+                            });
 
-                if let Some(redis_val) = redis_key_val_store.lock().unwrap().get_mut(db_list_name) {
                     #[expect(
                         clippy::match_wildcard_for_single_variants,
-                        reason = "lbpop command works only on List"
+                        reason = "lpush command works only on List"
                     )]
-                    match redis_val.data {
+                    // Insert the desired data to the referenced value, taking care of errors
+                    let insertion_result: Result<usize, &str> = match redis_val.data {
                         RedisType::List(ref mut list) => {
-                            let val = list.pop_front().unwrap();
-
-                            // Remove the key from the store if its list has become empty
-                            // if list.is_empty() {
-                            //     store.remove(db_list_name);
-                            // }
-                            // drop(store);
-                            &format!("${}\r\n{}\r\n", val.len(), val)
+                            if parsed_command.len() <= 2 {
+                                Err("ERR wrong number of arguments for command")
+                            } else {
+                                for x in parsed_command[2..].iter().cloned() {
+                                    list.push_front(x);
+                                }
+
+                                // Wake exactly one blocked `BLPOP`/`BRPOP` waiter, if any.
+                                // See `wake_one_waiter`
+                                wake_one_waiter(&oneshot_store, &parsed_command[1]);
+
+                                Ok(list.len())
+                            }
                         }
-                        _ => {
-                            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+                        _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value"),
+                    };
+                    drop(store);
+
+                    if insertion_result.is_ok() {
+                        replication.propagate(&parsed_command);
+                    }
+
+                    // Convert to RESP and return the result
+                    match insertion_result {
+                        Ok(len) => &format!(":{len}\r\n"),
+                        Err(err) => &format!("-{err}\r\n"),
+                    }
+                }
+                "lrange" => {
+                    let lrange_output = lrange(&redis_key_val_store, &parsed_command);
+
+                    // Convert to RESP and return the result
+                    match lrange_output {
+                        Ok(output_array) => {
+                            let output_string = format!("*{}\r\n", output_array.len());
+                            &output_array.iter().fold(output_string.clone(), |acc, x| {
+                                acc + "$" + x.len().to_string().as_str() + "\r\n" + x + "\r\n"
+                            })
                         }
+                        Err(err) => &format!("-{err}\r\n"),
                     }
-                } else {
-                    // let mut oneshot_store = oneshot_store.lock().await;
-                    let mut oneshot_store = oneshot_store.lock().unwrap();
-                    let channel = tokio::sync::oneshot::channel();
-
-                    let oneshot_val = oneshot_store.entry(db_list_name.clone()).or_default();
-                    oneshot_val.push_back(channel.0);
-                    drop(oneshot_store);
-
-                    channel.1.await;
-
-                    // if let Some(redis_val) = store.get_mut(db_list_name) {
-                    //     match redis_val.data {
-                    //         RedisType::List(ref mut list) => {
-                    //             let val = list.pop_front().unwrap();
-                    //
-                    //                 // Remove the key from the store if its list has become empty
-                    //                 if list.is_empty() {
-                    //                     store.remove(db_list_name);
-                    //                 }
-                    //                 drop(store);
-                    //             &format!("${}\r\n{}\r\n", val.len(), val)
-                    //         }
-                    //         _ => {
-                    //             "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
-                    //         }
-                    //     };
-                    // }
-
-                    "$1\r\n"
                 }
-            }
-            _ => {
-                // Handle case of unknown command
-                let args = parsed_command
-                    .iter()
-                    .skip(1) // First element is the command so skip it
-                    .fold(String::new(), |acc, x| acc + "`" + x + "`, ");
-                &format!(
-                    "-ERR unknown command `{}`, with args beginning with: {}\r\n",
-                    parsed_command[0], args,
-                )
-            }
+                "llen" => {
+                    let store = redis_key_val_store.lock().unwrap();
+
+                    #[expect(clippy::match_wildcard_for_single_variants, reason = "llen command works only on List")]
+                    &store
+                        .get(parsed_command[1].as_str())
+                        .map_or(Cow::Borrowed(":0\r\n"), |redis_val| match redis_val.data {
+                            RedisType::List(ref list) => Cow::Owned(format!(":{}\r\n", list.len())),
+                            _ => {
+                                Cow::Borrowed("-WRONGTYPE Operation against a key holding the wrong kind of value\r\n")
+                            }
+                        })
+                }
+                "lpop" => {
+                    let mut store = redis_key_val_store.lock().unwrap();
+                    let times_to_pop = parsed_command
+                        .get(2)
+                        .map_or(1, |x| x.parse::<u32>().unwrap());
+
+                    if let Some(redis_val) = store.get_mut(parsed_command[1].as_str()) {
+                        #[expect(
+                            clippy::match_wildcard_for_single_variants,
+                            reason = "lpop command works only on List"
+                        )]
+                        match redis_val.data {
+                            RedisType::List(ref mut list) => {
+                                let output_array: Vec<String> =
+                                    (1..=times_to_pop).map_while(|_| list.pop_front()).collect();
+
+                                // Remove the key from the store if its list has become empty
+                                if list.is_empty() {
+                                    store.remove(&parsed_command[1]);
+                                }
+                                drop(store);
+
+                                if !output_array.is_empty() {
+                                    replication.propagate(&parsed_command);
+                                }
+
+                                if output_array.is_empty() {
+                                    "$-1\r\n"
+                                } else if output_array.len() == 1 {
+                                    &format!("${}\r\n{}\r\n", output_array[0].len(), output_array[0])
+                                } else {
+                                    let output_string = format!("*{}\r\n", output_array.len());
+                                    &output_array.iter().fold(output_string.clone(), |acc, x| {
+                                        acc + "$" + x.len().to_string().as_str() + "\r\n" + x + "\r\n"
+                                    })
+                                }
+                            }
+                            _ => {
+                                "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+                            }
+                        }
+                    } else {
+                        "$-1\r\n"
+                    }
+                }
+                "blpop" => {
+                    // `BLPOP key [key ...] timeout`: the last argument is
+                    // always the timeout, everything in between is a key.
+                    let timeout_secs = parsed_command.last().and_then(|arg| arg.parse::<f64>().ok());
+                    let keys = &parsed_command[1..parsed_command.len() - 1];
+                    &blocking_list_pop(
+                        &redis_key_val_store,
+                        &oneshot_store,
+                        &replication,
+                        keys,
+                        timeout_secs,
+                        true,
+                    )
+                    .await
+                }
+                "brpop" => {
+                    // `BRPOP key [key ...] timeout`, identical to `BLPOP` but
+                    // pops from the back of whichever key has an element.
+                    let timeout_secs = parsed_command.last().and_then(|arg| arg.parse::<f64>().ok());
+                    let keys = &parsed_command[1..parsed_command.len() - 1];
+                    &blocking_list_pop(
+                        &redis_key_val_store,
+                        &oneshot_store,
+                        &replication,
+                        keys,
+                        timeout_secs,
+                        false,
+                    )
+                    .await
+                }
+                _ => {
+                    // Handle case of unknown command
+                    let args = parsed_command
+                        .iter()
+                        .skip(1) // First element is the command so skip it
+                        .fold(String::new(), |acc, x| acc + "`" + x + "`, ");
+                    &format!(
+                        "-ERR unknown command `{}`, with args beginning with: {}\r\n",
+                        parsed_command[0], args,
+                    )
+                }
+            };
+
+            pipelined_output.extend_from_slice(redis_output.as_bytes());
+        }
+
+        if !pipelined_output.is_empty() && stream.write_all(&pipelined_output).await.is_err() {
+            break;
+        }
+
+        // Shift any trailing partial frame to the front so the next read
+        // lands right after it, keeping per-connection memory bounded.
+        buf.copy_within(consumed..filled, 0);
+        filled -= consumed;
+    }
+}
+
+/// Resolve a `--bind` spec into a `SocketAddr`. Accepts a bare IPv4/IPv6
+/// address (port defaults to `default_port`), a bracketed IPv6 address with
+/// an optional `:port` suffix (e.g. `[::1]:6379`), or a plain `host:port`.
+fn parse_bind_spec(spec: &str, default_port: u16) -> Option<SocketAddr> {
+    if let Some(rest) = spec.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        let ip: IpAddr = host.parse().ok()?;
+        let port = match after.strip_prefix(':') {
+            Some(port_str) => port_str.parse().ok()?,
+            None => default_port,
         };
+        return Some(SocketAddr::new(ip, port));
+    }
+    if let Ok(ip) = spec.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, default_port));
+    }
+    let (host, port) = spec.rsplit_once(':')?;
+    Some(SocketAddr::new(host.parse().ok()?, port.parse().ok()?))
+}
 
-        stream.write_all(redis_output.as_bytes()).await.unwrap();
+/// Accept connections from `listener`, spawning a [`process`] task for each
+/// one against the shared keyspace and server state, until `shutdown` is
+/// triggered, at which point this stops taking new connections and returns.
+#[expect(
+    clippy::too_many_arguments,
+    reason = "Mirrors the handful of pieces of shared state `process` already threads through"
+)]
+async fn serve(
+    listener: TcpListener,
+    redis_key_val_store: Arc<Mutex<HashMap<String, RedisValue>>>,
+    oneshot_store: Arc<Mutex<HashMap<String, VecDeque<Waiter>>>>,
+    ttl_keys_store: Arc<Mutex<HashSet<String>>>,
+    pubsub_broker: pubsub::Broker,
+    stats: stats::SharedStats,
+    replication: replication::SharedReplication,
+    shutdown: shutdown::SharedShutdown,
+    my_port: u16,
+) {
+    loop {
+        tokio::select! {
+            () = shutdown.wait() => return,
+            accept_result = listener.accept() => {
+                match accept_result {
+                    // The second item contains the IP and port of the new connection.
+                    Ok((stream, _)) => {
+                        let redis_key_val_store = Arc::clone(&redis_key_val_store);
+                        let oneshot_store = Arc::clone(&oneshot_store);
+                        let ttl_keys_store = Arc::clone(&ttl_keys_store);
+                        let pubsub_broker = Arc::clone(&pubsub_broker);
+                        let stats = Arc::clone(&stats);
+                        let replication = Arc::clone(&replication);
+                        let connection_guard = shutdown::ConnectionGuard::new(Arc::clone(&shutdown));
+                        let shutdown = Arc::clone(&shutdown);
+
+                        // A new task is spawned for each inbound socket. The socket is
+                        // moved to the new task and processed there.
+                        tokio::spawn(async move {
+                            let _guard = connection_guard;
+                            process(
+                                stream,
+                                redis_key_val_store,
+                                oneshot_store,
+                                ttl_keys_store,
+                                pubsub_broker,
+                                stats,
+                                replication,
+                                shutdown,
+                                my_port,
+                            )
+                            .await;
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("error: {e}");
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -516,10 +1035,42 @@ async fn main() -> ! {
         args.push("6379".to_string());
     }
     let port = args[1].as_str();
+    let my_port: u16 = port.parse().unwrap();
+
+    // `--replicaof <host> <port>` starts this server up already acting as a
+    // replica of the given master, instead of waiting for a `REPLICAOF`
+    // command.
+    let replicaof = args
+        .iter()
+        .position(|arg| arg == "--replicaof")
+        .and_then(|i| Some((args.get(i + 1)?.clone(), args.get(i + 2)?.parse::<u16>().ok()?)));
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
-        .await
-        .unwrap();
+    // `--bind <spec>` is repeatable; each spec becomes its own listener, all
+    // feeding into the same shared keyspace. Defaults to IPv4 and IPv6
+    // loopback so the server is reachable over both without extra flags.
+    let bind_specs: Vec<String> = args
+        .iter()
+        .enumerate()
+        .filter_map(|(i, arg)| (arg == "--bind").then(|| args.get(i + 1).cloned()).flatten())
+        .collect();
+    let bind_specs = if bind_specs.is_empty() {
+        vec!["127.0.0.1".to_owned(), "[::1]".to_owned()]
+    } else {
+        bind_specs
+    };
+    let bind_addrs: Vec<SocketAddr> = bind_specs
+        .iter()
+        .filter_map(|spec| parse_bind_spec(spec, my_port))
+        .collect();
+
+    let mut listeners = Vec::with_capacity(bind_addrs.len());
+    for addr in bind_addrs {
+        listeners.push(TcpListener::bind(addr).await.unwrap());
+    }
+    // Non-empty unless every `--bind` spec given failed to parse (the
+    // loopback defaults above only apply when no `--bind` is passed at all),
+    // in which case this panics.
+    let last_listener = listeners.pop().unwrap();
 
     // Actual Redis key-val store
     let redis_key_val_store: Arc<Mutex<HashMap<String, RedisValue>>> =
@@ -528,32 +1079,79 @@ async fn main() -> ! {
     // let oneshot_store: Arc<Mutex<HashMap<String, VecDeque<(Sender<()>, Receiver<()>)>>>> =
     //     Arc::new(Mutex::new(HashMap::new()));
     // let oneshot_store: Arc<TMutex<HashMap<String, VecDeque<Sender<()>>>>> =
-    let oneshot_store: Arc<Mutex<HashMap<String, VecDeque<Sender<()>>>>> =
+    let oneshot_store: Arc<Mutex<HashMap<String, VecDeque<Waiter>>>> =
         Arc::new(Mutex::new(HashMap::new()));
 
-    // // Handle "ACTIVE EXPIRY" of keys
-    // let store = redis_key_val_store.clone();
-    // tokio::spawn(async move {
-    //     delete_expired_keys(store).await;
-    // });
+    // Side set tracking only the keys that carry a TTL, so active expiry can
+    // sample cheaply instead of scanning the whole keyspace.
+    let ttl_keys_store: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
 
-    loop {
-        match listener.accept().await {
-            // The second item contains the IP and port of the new connection.
-            Ok((stream, _)) => {
-                let redis_key_val_store = Arc::clone(&redis_key_val_store); // Same as .clone()
-                                                                            // let oneshot_store = oneshot_store.clone();
-                let oneshot_store = Arc::clone(&oneshot_store);
-
-                // A new task is spawned for each inbound socket. The socket is
-                // moved to the new task and processed there.
-                tokio::spawn(async move {
-                    process(stream, redis_key_val_store, oneshot_store).await;
-                });
-            }
-            Err(e) => {
-                eprintln!("error: {e}");
-            }
-        }
+    // Publish/subscribe channel registry, shared by every connection.
+    let pubsub_broker: pubsub::Broker = pubsub::new_broker();
+
+    // Server-wide statistics, rendered back out by the `INFO` command.
+    let stats: stats::SharedStats = stats::Stats::new_shared();
+
+    // Replication role/offset, shared by every connection and by the
+    // replica-side connection to our master, if we have one.
+    let replication: replication::SharedReplication = replication::Replication::new_shared();
+    if let Some((host, master_port)) = replicaof {
+        replication.become_replica(host.clone(), master_port);
+        tokio::spawn(replication::run_replica(
+            host,
+            master_port,
+            my_port,
+            Arc::clone(&redis_key_val_store),
+            Arc::clone(&ttl_keys_store),
+            Arc::clone(&oneshot_store),
+        ));
     }
+
+    // Handle "ACTIVE EXPIRY" of keys
+    {
+        let redis_key_val_store = Arc::clone(&redis_key_val_store);
+        let ttl_keys_store = Arc::clone(&ttl_keys_store);
+        let stats = Arc::clone(&stats);
+        tokio::spawn(async move {
+            delete_expired_keys(redis_key_val_store, ttl_keys_store, stats).await;
+        });
+    }
+
+    // Cooperative shutdown: triggered by the `SHUTDOWN` command or by
+    // SIGTERM/SIGINT, observed by every accept loop below.
+    let shutdown: shutdown::SharedShutdown = shutdown::Shutdown::new_shared();
+    shutdown::install_signal_handlers(Arc::clone(&shutdown));
+
+    // Every listener but the last runs on its own task; the last one runs
+    // directly on the main task. Both stop as soon as `shutdown` fires.
+    for listener in listeners {
+        tokio::spawn(serve(
+            listener,
+            Arc::clone(&redis_key_val_store),
+            Arc::clone(&oneshot_store),
+            Arc::clone(&ttl_keys_store),
+            Arc::clone(&pubsub_broker),
+            Arc::clone(&stats),
+            Arc::clone(&replication),
+            Arc::clone(&shutdown),
+            my_port,
+        ));
+    }
+    serve(
+        last_listener,
+        redis_key_val_store,
+        oneshot_store,
+        ttl_keys_store,
+        pubsub_broker,
+        stats,
+        replication,
+        Arc::clone(&shutdown),
+        my_port,
+    )
+    .await;
+
+    // Give in-flight connections a chance to finish on their own, then exit
+    // cleanly rather than being killed.
+    shutdown.drain().await;
+    std::process::exit(0);
 }
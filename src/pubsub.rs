@@ -0,0 +1,449 @@
+//! Publish/subscribe broker.
+//!
+//! Channels are backed by `tokio::sync::broadcast` so every live subscriber
+//! gets its own copy of each published message. Each channel's queue has a
+//! bounded capacity, but every subscribed connection's forwarder tasks move
+//! messages off it straight away into that connection's unbounded outgoing
+//! queue, so a subscriber that is slow to read its socket is not limited by
+//! that capacity and its pending messages are not dropped. Pattern
+//! subscriptions (`PSUBSCRIBE`) work the same way, keyed by the raw glob
+//! pattern text instead of a literal channel name; `PUBLISH` matches the
+//! channel it is sending on against every registered pattern.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+    sync::{broadcast, mpsc, Mutex as TMutex},
+    task::JoinHandle,
+};
+
+use crate::{parse_command, ParsedFrame, READ_CHUNK_SIZE};
+
+/// Bounded capacity of each channel's and pattern's broadcast queue.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// The registries backing `SUBSCRIBE`/`PSUBSCRIBE`, created lazily the first
+/// time something subscribes to (or publishes on) a given channel/pattern.
+#[derive(Default)]
+pub struct BrokerInner {
+    /// Exact-match channels, keyed by channel name.
+    channels: HashMap<String, broadcast::Sender<String>>,
+    /// Pattern subscriptions, keyed by the raw glob pattern text. The
+    /// payload carries the channel name alongside the message, since one
+    /// pattern can match many channels.
+    patterns: HashMap<String, broadcast::Sender<(String, String)>>,
+}
+
+/// Shared registry of channel/pattern name -> its broadcast sender.
+pub type Broker = Arc<TMutex<BrokerInner>>;
+
+/// Create an empty broker.
+pub fn new_broker() -> Broker {
+    Arc::new(TMutex::new(BrokerInner::default()))
+}
+
+/// Publish `message` on `channel`, delivering it to every exact subscriber
+/// of `channel` and every subscriber whose pattern matches it. Returns how
+/// many subscribers received it in total.
+pub async fn publish(broker: &Broker, channel: &str, message: &str) -> usize {
+    let broker = broker.lock().await;
+
+    let exact_count = broker
+        .channels
+        .get(channel)
+        .map_or(0, |sender| sender.send(message.to_owned()).unwrap_or(0));
+
+    let pattern_count: usize = broker
+        .patterns
+        .iter()
+        .filter(|(pattern, _)| glob_match(pattern, channel))
+        .map(|(_, sender)| {
+            sender
+                .send((channel.to_owned(), message.to_owned()))
+                .unwrap_or(0)
+        })
+        .sum();
+
+    exact_count + pattern_count
+}
+
+/// Subscribe to `channel`, creating it if this is the first subscriber.
+async fn subscribe_to(broker: &Broker, channel: &str) -> broadcast::Receiver<String> {
+    let mut broker = broker.lock().await;
+    broker
+        .channels
+        .entry(channel.to_owned())
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+}
+
+/// Subscribe to `pattern`, creating it if this is the first subscriber.
+async fn subscribe_to_pattern(
+    broker: &Broker,
+    pattern: &str,
+) -> broadcast::Receiver<(String, String)> {
+    let mut broker = broker.lock().await;
+    broker
+        .patterns
+        .entry(pattern.to_owned())
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe()
+}
+
+/// Match `channel` against a Redis-style glob `pattern`: `*` matches any run
+/// of characters, `?` matches exactly one, and `[...]` matches any single
+/// character in the (optionally negated, `[^...]`) set, including `a-z`
+/// style ranges.
+fn glob_match(pattern: &str, channel: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let channel: Vec<char> = channel.chars().collect();
+    glob_match_from(&pattern, &channel)
+}
+
+/// Recursive glob matcher over already-collected character slices.
+fn glob_match_from(pattern: &[char], channel: &[char]) -> bool {
+    match pattern.first() {
+        None => channel.is_empty(),
+        Some('*') => {
+            glob_match_from(&pattern[1..], channel)
+                || (!channel.is_empty() && glob_match_from(pattern, &channel[1..]))
+        }
+        Some('?') => !channel.is_empty() && glob_match_from(&pattern[1..], &channel[1..]),
+        Some('[') => {
+            let Some(close) = pattern.iter().position(|&c| c == ']').filter(|&i| i > 0) else {
+                // No closing bracket: treat `[` as a literal character.
+                return !channel.is_empty()
+                    && channel[0] == '['
+                    && glob_match_from(&pattern[1..], &channel[1..]);
+            };
+            if channel.is_empty() {
+                return false;
+            }
+            let negated = pattern.get(1) == Some(&'^');
+            let set = &pattern[usize::from(negated) + 1..close];
+            if char_in_set(set, channel[0]) != negated {
+                glob_match_from(&pattern[close + 1..], &channel[1..])
+            } else {
+                false
+            }
+        }
+        Some(&literal) => {
+            !channel.is_empty() && channel[0] == literal && glob_match_from(&pattern[1..], &channel[1..])
+        }
+    }
+}
+
+/// Whether `ch` is a member of bracket-expression `set`, expanding `a-z`
+/// style ranges.
+fn char_in_set(set: &[char], ch: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            if set[i] <= ch && ch <= set[i + 2] {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == ch {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// One message handed from a forwarder task to a subscriber connection's
+/// writer, over that connection's `forward` channel.
+enum Delivery {
+    /// An exact-channel `SUBSCRIBE` match.
+    Message { channel: String, payload: String },
+    /// A `PSUBSCRIBE` pattern match; `pattern` is the subscription that
+    /// matched, `channel` is the channel the message was actually published on.
+    PMessage {
+        pattern: String,
+        channel: String,
+        payload: String,
+    },
+}
+
+/// RESP-encode a bulk string.
+fn bulk_string(value: &str) -> String {
+    format!("${}\r\n{value}\r\n", value.len())
+}
+
+/// RESP-encode the three-element array `(P)SUBSCRIBE`/`(P)UNSUBSCRIBE`
+/// confirms with.
+fn confirmation(kind: &str, name: &str, count: usize) -> String {
+    format!("*3\r\n{}{}:{count}\r\n", bulk_string(kind), bulk_string(name))
+}
+
+/// Forwarder handles for one connection's subscriptions, so `UNSUBSCRIBE`
+/// and disconnect can stop the right background tasks.
+#[derive(Default)]
+struct Subscriptions {
+    /// Exact-channel subscriptions, keyed by channel name.
+    channels: HashMap<String, JoinHandle<()>>,
+    /// Pattern subscriptions, keyed by the raw pattern text.
+    patterns: HashMap<String, JoinHandle<()>>,
+}
+
+impl Subscriptions {
+    /// Total number of live subscriptions, across channels and patterns.
+    fn total(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+/// Start forwarding `channel`'s published messages to this connection,
+/// unless it is already subscribed, and append the RESP confirmation array
+/// to `reply`.
+async fn subscribe_channel(
+    broker: &Broker,
+    channel: &str,
+    forward: &mpsc::UnboundedSender<Delivery>,
+    subscriptions: &mut Subscriptions,
+    reply: &mut Vec<u8>,
+) {
+    if let Entry::Vacant(entry) = subscriptions.channels.entry(channel.to_owned()) {
+        let mut receiver = subscribe_to(broker, channel).await;
+        let forward = forward.clone();
+        let channel_owned = channel.to_owned();
+        entry.insert(tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(payload) => {
+                        let delivery = Delivery::Message {
+                            channel: channel_owned.clone(),
+                            payload,
+                        };
+                        if forward.send(delivery).is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }));
+    }
+    reply.extend_from_slice(confirmation("subscribe", channel, subscriptions.total()).as_bytes());
+}
+
+/// Start forwarding messages on any channel matching `pattern` to this
+/// connection, unless it is already subscribed, and append the RESP
+/// confirmation array to `reply`.
+async fn subscribe_pattern(
+    broker: &Broker,
+    pattern: &str,
+    forward: &mpsc::UnboundedSender<Delivery>,
+    subscriptions: &mut Subscriptions,
+    reply: &mut Vec<u8>,
+) {
+    if let Entry::Vacant(entry) = subscriptions.patterns.entry(pattern.to_owned()) {
+        let mut receiver = subscribe_to_pattern(broker, pattern).await;
+        let forward = forward.clone();
+        let pattern_owned = pattern.to_owned();
+        entry.insert(tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok((channel, payload)) => {
+                        let delivery = Delivery::PMessage {
+                            pattern: pattern_owned.clone(),
+                            channel,
+                            payload,
+                        };
+                        if forward.send(delivery).is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }));
+    }
+    reply.extend_from_slice(confirmation("psubscribe", pattern, subscriptions.total()).as_bytes());
+}
+
+/// Parse and handle every complete frame currently sitting in
+/// `buf[..*filled]`, writing out accumulated replies in one go and
+/// compacting the buffer afterwards. Returns `false` if the connection
+/// should be closed (write failure, or the client sent `QUIT`).
+async fn drain_commands(
+    stream: &mut TcpStream,
+    broker: &Broker,
+    forward: &mpsc::UnboundedSender<Delivery>,
+    subscriptions: &mut Subscriptions,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> bool {
+    let mut consumed = 0;
+    let mut reply = Vec::new();
+    while let ParsedFrame::Complete { args, consumed: frame_len } = parse_command(&buf[consumed..*filled]) {
+        consumed += frame_len;
+        if args.is_empty() {
+            continue;
+        }
+        let command: Vec<String> = args
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+
+        match command[0].to_lowercase().as_str() {
+            "subscribe" => {
+                for channel in &command[1..] {
+                    subscribe_channel(broker, channel, forward, subscriptions, &mut reply).await;
+                }
+            }
+            "psubscribe" => {
+                for pattern in &command[1..] {
+                    subscribe_pattern(broker, pattern, forward, subscriptions, &mut reply).await;
+                }
+            }
+            "unsubscribe" => {
+                let targets = if command.len() > 1 {
+                    command[1..].to_vec()
+                } else {
+                    subscriptions.channels.keys().cloned().collect()
+                };
+                for channel in targets {
+                    if let Some(handle) = subscriptions.channels.remove(&channel) {
+                        handle.abort();
+                    }
+                    reply.extend_from_slice(confirmation("unsubscribe", &channel, subscriptions.total()).as_bytes());
+                }
+            }
+            "punsubscribe" => {
+                let targets = if command.len() > 1 {
+                    command[1..].to_vec()
+                } else {
+                    subscriptions.patterns.keys().cloned().collect()
+                };
+                for pattern in targets {
+                    if let Some(handle) = subscriptions.patterns.remove(&pattern) {
+                        handle.abort();
+                    }
+                    reply.extend_from_slice(confirmation("punsubscribe", &pattern, subscriptions.total()).as_bytes());
+                }
+            }
+            "ping" => reply.extend_from_slice(b"+PONG\r\n"),
+            "quit" => {
+                let _ = stream.write_all(b"+OK\r\n").await;
+                return false;
+            }
+            other => reply.extend_from_slice(
+                format!(
+                    "-ERR Can't execute '{other}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT are allowed in this context\r\n"
+                )
+                .as_bytes(),
+            ),
+        }
+    }
+    if !reply.is_empty() && stream.write_all(&reply).await.is_err() {
+        return false;
+    }
+    buf.copy_within(consumed..*filled, 0);
+    *filled -= consumed;
+    true
+}
+
+/// Take over a connection that just issued `SUBSCRIBE`/`PSUBSCRIBE`, keeping
+/// it in subscriber mode until it disconnects.
+///
+/// Multiplexes the socket's read half against every subscribed
+/// channel/pattern's receiver via `select!`, so `message`/`pmessage` frames
+/// pushed from other connections' `PUBLISH`es are written out as soon as
+/// they arrive, while the client is still free to `SUBSCRIBE`/`PSUBSCRIBE`/
+/// `UNSUBSCRIBE`/`PUNSUBSCRIBE` further channels or `PING`.
+pub async fn run(
+    mut stream: TcpStream,
+    broker: Broker,
+    initial_channels: Vec<String>,
+    initial_patterns: Vec<String>,
+    leftover: Vec<u8>,
+) {
+    // Every subscribed channel/pattern gets its own forwarder task (see
+    // `subscribe_channel`/`subscribe_pattern`) that pushes a `Delivery` here;
+    // the select loop below is what actually writes them out as
+    // `message`/`pmessage` frames.
+    let (forward, mut incoming) = mpsc::unbounded_channel::<Delivery>();
+    let mut subscriptions = Subscriptions::default();
+
+    let mut reply = Vec::new();
+    for channel in initial_channels {
+        subscribe_channel(&broker, &channel, &forward, &mut subscriptions, &mut reply).await;
+    }
+    for pattern in initial_patterns {
+        subscribe_pattern(&broker, &pattern, &forward, &mut subscriptions, &mut reply).await;
+    }
+    if stream.write_all(&reply).await.is_err() {
+        return;
+    }
+
+    // Seed the buffer with any bytes already read off the socket before the
+    // handoff into this function (e.g. a pipelined command that arrived in
+    // the same TCP segment as the `SUBSCRIBE`), so they aren't silently
+    // dropped in favor of only reading fresh ones.
+    let mut buf = vec![0_u8; READ_CHUNK_SIZE.max(leftover.len())];
+    buf[..leftover.len()].copy_from_slice(&leftover);
+    let mut filled = leftover.len();
+
+    // A pipelined command may already sit fully-formed in the seeded bytes
+    // above, with nothing more arriving on the socket for a while (or ever);
+    // handle it before blocking on the first read.
+    if filled > 0
+        && !drain_commands(&mut stream, &broker, &forward, &mut subscriptions, &mut buf, &mut filled).await
+    {
+        return;
+    }
+
+    loop {
+        if buf.len() < filled + READ_CHUNK_SIZE {
+            buf.resize(filled + READ_CHUNK_SIZE, 0);
+        }
+        tokio::select! {
+            read_result = stream.read(&mut buf[filled..]) => {
+                let Ok(bytes_read) = read_result else { break };
+                if bytes_read == 0 {
+                    break;
+                }
+                filled += bytes_read;
+
+                if !drain_commands(&mut stream, &broker, &forward, &mut subscriptions, &mut buf, &mut filled).await {
+                    break;
+                }
+            }
+            Some(delivery) = incoming.recv() => {
+                let frame = match delivery {
+                    Delivery::Message { channel, payload } => format!(
+                        "*3\r\n$7\r\nmessage\r\n{}{}",
+                        bulk_string(&channel),
+                        bulk_string(&payload),
+                    ),
+                    Delivery::PMessage { pattern, channel, payload } => format!(
+                        "*4\r\n$8\r\npmessage\r\n{}{}{}",
+                        bulk_string(&pattern),
+                        bulk_string(&channel),
+                        bulk_string(&payload),
+                    ),
+                };
+                if stream.write_all(frame.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    for (_, handle) in subscriptions.channels {
+        handle.abort();
+    }
+    for (_, handle) in subscriptions.patterns {
+        handle.abort();
+    }
+}
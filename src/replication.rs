@@ -0,0 +1,525 @@
+//! Master-replica replication.
+//!
+//! A master tracks its connected replicas as subscribers of a
+//! `tokio::sync::broadcast` channel of serialized write commands, mirroring
+//! the pub/sub broker's fan-out design: every `PSYNC`'d connection
+//! subscribes once, then simply forwards whatever comes out of the channel
+//! to its replica socket. A replica instead connects out to its master,
+//! performs the handshake, and applies the stream of write commands it
+//! receives directly to its own keyspace.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+    sync::broadcast,
+    time as tokio_time,
+};
+
+use crate::{parse_command, wake_one_waiter, ParsedFrame, RedisType, RedisValue, Waiter, READ_CHUNK_SIZE};
+
+/// Bounded capacity of the propagation channel every replica subscribes to.
+const PROPAGATION_CAPACITY: usize = 1024;
+/// How often `WAIT` re-checks acked offsets while polling for replicas to
+/// catch up.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// This server's replication role.
+pub enum Role {
+    /// Acting as a master.
+    Master,
+    /// Acting as a replica of `host:port`.
+    Replica {
+        /// Hostname or IP address of the master.
+        host: String,
+        /// Port the master is listening on.
+        port: u16,
+    },
+}
+
+/// Shared replication state, cloned into every connection task.
+pub struct Replication {
+    /// Current role; changed by `REPLICAOF`.
+    role: Mutex<Role>,
+    /// This server's 40-character replication ID, generated once at startup.
+    replid: String,
+    /// Master replication offset: total bytes of command stream propagated.
+    offset: AtomicU64,
+    /// Every connected replica subscribes to this to receive propagated
+    /// writes as they happen.
+    propagate: broadcast::Sender<Vec<u8>>,
+    /// Offset each connected replica last acked via `REPLCONF ACK`, keyed by
+    /// a per-connection id handed out from `next_replica_id`.
+    acked_offsets: Mutex<HashMap<u64, u64>>,
+    /// Source of the per-connection ids used as keys into `acked_offsets`.
+    next_replica_id: AtomicU64,
+}
+
+/// Shared handle to the replication state, cloned into every connection task.
+pub type SharedReplication = Arc<Replication>;
+
+impl Replication {
+    /// Create a fresh, shared replication handle starting out as a master.
+    pub fn new_shared() -> SharedReplication {
+        Arc::new(Self {
+            role: Mutex::new(Role::Master),
+            replid: generate_replid(),
+            offset: AtomicU64::new(0),
+            propagate: broadcast::channel(PROPAGATION_CAPACITY).0,
+            acked_offsets: Mutex::new(HashMap::new()),
+            next_replica_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Switch to acting as a replica of `host:port`.
+    pub fn become_replica(&self, host: String, port: u16) {
+        *self.role.lock().unwrap() = Role::Replica { host, port };
+    }
+
+    /// Switch to acting as a master (`REPLICAOF NO ONE`).
+    pub fn become_master(&self) {
+        *self.role.lock().unwrap() = Role::Master;
+    }
+
+    /// Serialize `command` as a RESP array and forward it to every
+    /// connected replica, advancing the master replication offset by the
+    /// number of bytes sent.
+    pub fn propagate(&self, command: &[String]) {
+        let encoded = encode_command(command);
+        // `usize -> u64` only ever widens, so there's no `cast_possible_truncation` to suppress here.
+        self.offset
+            .fetch_add(encoded.len() as u64, Ordering::Relaxed);
+        // No receivers just means no replica is connected right now; that's
+        // not an error, so ignore the send failure.
+        let _ = self.propagate.send(encoded);
+    }
+
+    /// Subscribe to the stream of propagated write commands, as raw
+    /// already-encoded RESP bytes.
+    fn subscribe(&self) -> broadcast::Receiver<Vec<u8>> {
+        self.propagate.subscribe()
+    }
+
+    /// Register a newly `PSYNC`'d replica connection, returning the id
+    /// `handle_psync` should use to report its acked offset.
+    fn register_replica(&self) -> u64 {
+        let id = self.next_replica_id.fetch_add(1, Ordering::Relaxed);
+        self.acked_offsets.lock().unwrap().insert(id, 0);
+        id
+    }
+
+    /// Record that replica `id` has processed the stream up to `offset`.
+    fn record_ack(&self, id: u64, offset: u64) {
+        self.acked_offsets.lock().unwrap().insert(id, offset);
+    }
+
+    /// Forget about replica `id` once its connection closes.
+    fn unregister_replica(&self, id: u64) {
+        self.acked_offsets.lock().unwrap().remove(&id);
+    }
+
+    /// Count how many connected replicas have acked at least `target_offset`.
+    fn count_acked(&self, target_offset: u64) -> usize {
+        self.acked_offsets
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|&&acked| acked >= target_offset)
+            .count()
+    }
+
+    /// Ask every connected replica to report its current offset, by
+    /// propagating a `REPLCONF GETACK *` command through the same channel as
+    /// ordinary writes.
+    fn request_ack(&self) {
+        self.propagate(&["REPLCONF".to_owned(), "GETACK".to_owned(), "*".to_owned()]);
+    }
+
+    /// Block until at least `numreplicas` replicas have acked the offset the
+    /// master is at right now, or until `timeout_ms` elapses (`0` meaning
+    /// wait indefinitely). Returns the number of replicas that had acked by
+    /// the time this returns.
+    pub async fn wait_for_acks(&self, numreplicas: usize, timeout_ms: u64) -> usize {
+        let target_offset = self.offset.load(Ordering::Relaxed);
+        if self.count_acked(target_offset) >= numreplicas {
+            return self.count_acked(target_offset);
+        }
+
+        self.request_ack();
+        let deadline = (timeout_ms > 0).then(|| tokio_time::Instant::now() + Duration::from_millis(timeout_ms));
+
+        loop {
+            let acked = self.count_acked(target_offset);
+            if acked >= numreplicas {
+                return acked;
+            }
+            if let Some(deadline) = deadline {
+                if tokio_time::Instant::now() >= deadline {
+                    return acked;
+                }
+            }
+            tokio_time::sleep(WAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Render the `# Replication` section of `INFO`.
+    pub fn render_info(&self) -> String {
+        let role_guard = self.role.lock().unwrap();
+        let (role, master_host_port) = match *role_guard {
+            Role::Master => ("master", String::new()),
+            Role::Replica { ref host, port } => {
+                ("slave", format!("master_host:{host}\r\nmaster_port:{port}\r\n"))
+            }
+        };
+        format!(
+            "# Replication\r\nrole:{role}\r\n{master_host_port}master_replid:{}\r\nmaster_repl_offset:{}\r\n\r\n",
+            self.replid,
+            self.offset.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Encode `command` as a RESP array of bulk strings, exactly as it would be
+/// sent over the wire.
+fn encode_command(command: &[String]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", command.len()).into_bytes();
+    for arg in command {
+        out.extend_from_slice(format!("${}\r\n{arg}\r\n", arg.len()).as_bytes());
+    }
+    out
+}
+
+/// Generate a 40-character lowercase-hex replication ID, matching the shape
+/// (though not the cryptographic strength) of real Redis's `runid`.
+fn generate_replid() -> String {
+    let mut seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+        | 1;
+    let mut replid = String::with_capacity(40);
+    for _ in 0..40 {
+        // Same constants as `SimpleRng` in `main.rs`; duplicated here since
+        // this is the only other place that needs randomness and pulling in
+        // a real RNG crate for 40 hex digits would be overkill.
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let nibble = (seed >> 33) % 16;
+        replid.push(char::from_digit(u32::try_from(nibble).unwrap(), 16).unwrap());
+    }
+    replid
+}
+
+/// Parse every complete `REPLCONF ACK <offset>` frame currently sitting in
+/// `buf[..*filled]` and record it, discarding anything else (a replica never
+/// sends us anything besides that), then compact the buffer.
+fn record_acks(replication: &SharedReplication, replica_id: u64, buf: &mut [u8], filled: &mut usize) {
+    let mut consumed = 0;
+    while let ParsedFrame::Complete { args, consumed: frame_len } = parse_command(&buf[consumed..*filled]) {
+        consumed += frame_len;
+        if let [cmd, sub, offset] = args.as_slice() {
+            if cmd.eq_ignore_ascii_case(b"replconf") && sub.eq_ignore_ascii_case(b"ack") {
+                if let Ok(offset) = String::from_utf8_lossy(offset).parse::<u64>() {
+                    replication.record_ack(replica_id, offset);
+                }
+            }
+        }
+    }
+    buf.copy_within(consumed..*filled, 0);
+    *filled -= consumed;
+}
+
+/// Handle a master-side `PSYNC`: reply with a `FULLRESYNC` line and an
+/// (empty, since this server has no RDB persistence) RDB bulk payload, then
+/// keep the connection open and forward every subsequently propagated write
+/// command to it until the replica disconnects. `leftover` is any bytes
+/// already read off the socket past the `PSYNC` frame itself (e.g. a
+/// `REPLCONF ACK` that arrived in the same segment), which otherwise would
+/// be silently dropped since this function starts with its own fresh buffer.
+pub async fn handle_psync(mut stream: TcpStream, replication: SharedReplication, leftover: Vec<u8>) {
+    let mut receiver = replication.subscribe();
+    let replica_id = replication.register_replica();
+
+    let fullresync = format!(
+        "+FULLRESYNC {} {}\r\n",
+        replication.replid,
+        replication.offset.load(Ordering::Relaxed),
+    );
+    // An empty RDB file: just the magic header and EOF opcode, with no
+    // trailing CRLF (unlike a normal bulk string) since this is a raw byte
+    // transfer rather than a RESP value.
+    let rdb: &[u8] = b"REDIS0011\xff\0\0\0\0\0\0\0\0";
+    let rdb_bulk = [format!("${}\r\n", rdb.len()).into_bytes(), rdb.to_vec()].concat();
+
+    if stream.write_all(fullresync.as_bytes()).await.is_err()
+        || stream.write_all(&rdb_bulk).await.is_err()
+    {
+        replication.unregister_replica(replica_id);
+        return;
+    }
+
+    let mut buf = vec![0_u8; READ_CHUNK_SIZE.max(leftover.len())];
+    buf[..leftover.len()].copy_from_slice(&leftover);
+    let mut filled = leftover.len();
+    if filled > 0 {
+        record_acks(&replication, replica_id, &mut buf, &mut filled);
+    }
+
+    loop {
+        tokio::select! {
+            propagated = receiver.recv() => {
+                let Ok(bytes) = propagated else { break };
+                if stream.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            // The only thing a replica sends back on this connection is its
+            // `REPLCONF ACK <offset>` reply to our `GETACK` requests.
+            read_result = stream.read(&mut buf[filled..]) => {
+                match read_result {
+                    Ok(0) | Err(_) => break,
+                    Ok(bytes_read) => {
+                        filled += bytes_read;
+                        if filled == buf.len() {
+                            buf.resize(buf.len() * 2, 0);
+                        }
+                        record_acks(&replication, replica_id, &mut buf, &mut filled);
+                    }
+                }
+            }
+        }
+    }
+    replication.unregister_replica(replica_id);
+}
+
+/// Read a single CRLF-terminated line from `stream`, without the trailing
+/// `\r\n`. Used only during the replica handshake, where replies are small
+/// simple-string/bulk-string lines rather than full RESP frames.
+async fn read_line(stream: &mut TcpStream) -> Option<String> {
+    let mut line = Vec::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        if stream.read_exact(&mut byte).await.is_err() {
+            return None;
+        }
+        if byte[0] == b'\n' {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            return Some(String::from_utf8_lossy(&line).into_owned());
+        }
+        line.push(byte[0]);
+    }
+}
+
+/// Connect to the master at `host:port`, perform the replication handshake,
+/// then apply every write command it streams afterwards to `store`. Runs
+/// for as long as this server remains a replica of that master; meant to be
+/// spawned as a background task.
+#[expect(
+    clippy::too_many_arguments,
+    reason = "Mirrors the handful of pieces of shared state `process` already threads through"
+)]
+pub async fn run_replica(
+    host: String,
+    port: u16,
+    my_port: u16,
+    store: Arc<Mutex<HashMap<String, RedisValue>>>,
+    ttl_keys_store: Arc<Mutex<HashSet<String>>>,
+    oneshot_store: Arc<Mutex<HashMap<String, VecDeque<Waiter>>>>,
+) {
+    let Ok(mut stream) = TcpStream::connect((host.as_str(), port)).await else {
+        return;
+    };
+
+    let handshake = [
+        "*1\r\n$4\r\nPING\r\n".to_owned(),
+        format!(
+            "*3\r\n$8\r\nREPLCONF\r\n$14\r\nlistening-port\r\n${}\r\n{my_port}\r\n",
+            my_port.to_string().len()
+        ),
+        "*3\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n".to_owned(),
+        "*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n".to_owned(),
+    ];
+    for step in handshake {
+        if stream.write_all(step.as_bytes()).await.is_err() {
+            return;
+        }
+        // Every handshake step gets exactly one simple-string reply line;
+        // for `PSYNC` that is the `+FULLRESYNC ...` line, and the RDB
+        // payload that follows it is skipped below.
+        if read_line(&mut stream).await.is_none() {
+            return;
+        }
+    }
+
+    // Skip the RDB bulk payload: `$<len>\r\n<len bytes, no trailing CRLF>`.
+    let Some(rdb_header) = read_line(&mut stream).await else {
+        return;
+    };
+    if let Some(len) = rdb_header.strip_prefix('$').and_then(|n| n.parse::<usize>().ok()) {
+        let mut remaining = len;
+        let mut discard = [0_u8; READ_CHUNK_SIZE];
+        while remaining > 0 {
+            let chunk = remaining.min(discard.len());
+            if stream.read_exact(&mut discard[..chunk]).await.is_err() {
+                return;
+            }
+            remaining -= chunk;
+        }
+    }
+
+    // From here on the master streams write commands as plain RESP arrays;
+    // apply each one to our own keyspace as it arrives, tracking how many
+    // bytes of the stream we've processed so we can answer `GETACK`.
+    let mut buf = vec![0_u8; READ_CHUNK_SIZE];
+    let mut filled = 0;
+    let mut replica_offset: u64 = 0;
+    loop {
+        if filled == buf.len() {
+            buf.resize(buf.len() * 2, 0);
+        }
+        let Ok(bytes_read) = stream.read(&mut buf[filled..]).await else {
+            return;
+        };
+        if bytes_read == 0 {
+            return;
+        }
+        filled += bytes_read;
+
+        let mut consumed = 0;
+        while let ParsedFrame::Complete { args, consumed: frame_len } = parse_command(&buf[consumed..filled]) {
+            consumed += frame_len;
+            // `usize -> u64` only ever widens, so there's no `cast_possible_truncation` to suppress here.
+            replica_offset += frame_len as u64;
+            if args.is_empty() {
+                continue;
+            }
+            if let [cmd, sub, _pattern] = args.as_slice() {
+                if cmd.eq_ignore_ascii_case(b"replconf") && sub.eq_ignore_ascii_case(b"getack") {
+                    let offset_str = replica_offset.to_string();
+                    let ack = format!(
+                        "*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{offset_str}\r\n",
+                        offset_str.len()
+                    );
+                    if stream.write_all(ack.as_bytes()).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            }
+            let command: Vec<String> = args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            apply_write_command(&store, &ttl_keys_store, &oneshot_store, &command);
+        }
+        buf.copy_within(consumed..filled, 0);
+        filled -= consumed;
+    }
+}
+
+/// Apply a single write command streamed from the master to our own
+/// keyspace. Covers the commands a master currently propagates (`SET`,
+/// `RPUSH`, `LPUSH`, `LPOP`, `RPOP`); anything else is ignored.
+fn apply_write_command(
+    store: &Arc<Mutex<HashMap<String, RedisValue>>>,
+    ttl_keys_store: &Arc<Mutex<HashSet<String>>>,
+    oneshot_store: &Arc<Mutex<HashMap<String, VecDeque<Waiter>>>>,
+    command: &[String],
+) {
+    let Some(name) = command.first() else { return };
+    match name.to_lowercase().as_str() {
+        "set" if command.len() >= 3 => {
+            let ttl = command
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case("px"))
+                .and_then(|i| command.get(i + 1))
+                .and_then(|arg| arg.parse::<u64>().ok());
+            if ttl.is_some() {
+                ttl_keys_store.lock().unwrap().insert(command[1].clone());
+            }
+            store.lock().unwrap().insert(
+                command[1].clone(),
+                RedisValue {
+                    data: RedisType::Val(command[2].clone()),
+                    creation_time: SystemTime::now(),
+                    ttl,
+                },
+            );
+        }
+        "rpush" if command.len() >= 3 => {
+            let mut store = store.lock().unwrap();
+            let redis_val = store.entry(command[1].clone()).or_insert_with(|| RedisValue {
+                data: RedisType::List(VecDeque::new()),
+                creation_time: SystemTime::now(),
+                ttl: None,
+            });
+            if let RedisType::List(ref mut list) = redis_val.data {
+                list.extend(command[2..].iter().cloned());
+            }
+            drop(store);
+            wake_one_waiter(oneshot_store, &command[1]);
+        }
+        "lpush" if command.len() >= 3 => {
+            let mut store = store.lock().unwrap();
+            let redis_val = store.entry(command[1].clone()).or_insert_with(|| RedisValue {
+                data: RedisType::List(VecDeque::new()),
+                creation_time: SystemTime::now(),
+                ttl: None,
+            });
+            if let RedisType::List(ref mut list) = redis_val.data {
+                for arg in command[2..].iter().cloned() {
+                    list.push_front(arg);
+                }
+            }
+            drop(store);
+            wake_one_waiter(oneshot_store, &command[1]);
+        }
+        "lpop" if command.len() >= 2 => {
+            let times_to_pop = command.get(2).and_then(|arg| arg.parse::<u32>().ok()).unwrap_or(1);
+            let mut store = store.lock().unwrap();
+            if let Some(redis_val) = store.get_mut(&command[1]) {
+                if let RedisType::List(ref mut list) = redis_val.data {
+                    for _ in 0..times_to_pop {
+                        if list.pop_front().is_none() {
+                            break;
+                        }
+                    }
+                    if list.is_empty() {
+                        store.remove(&command[1]);
+                    }
+                }
+            }
+        }
+        // `BRPOP`'s pop path propagates this as the equivalent non-blocking
+        // pop, since there's no standalone non-blocking `RPOP` command in
+        // the dispatcher.
+        "rpop" if command.len() >= 2 => {
+            let times_to_pop = command.get(2).and_then(|arg| arg.parse::<u32>().ok()).unwrap_or(1);
+            let mut store = store.lock().unwrap();
+            if let Some(redis_val) = store.get_mut(&command[1]) {
+                if let RedisType::List(ref mut list) = redis_val.data {
+                    for _ in 0..times_to_pop {
+                        if list.pop_back().is_none() {
+                            break;
+                        }
+                    }
+                    if list.is_empty() {
+                        store.remove(&command[1]);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,116 @@
+//! Cooperative shutdown, triggered either by the `SHUTDOWN` command or by
+//! the process receiving `SIGTERM`/`SIGINT`.
+//!
+//! Every accept loop observes a single shared flag, woken via a
+//! [`tokio::sync::Notify`] as soon as it's set, so the server stops taking
+//! new connections and gives in-flight ones a chance to finish before the
+//! process actually exits, instead of being killed outright.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::{
+    signal,
+    sync::Notify,
+    time::{self as tokio_time, Duration},
+};
+
+/// How long to wait for in-flight connections to finish on their own before
+/// exiting anyway.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often to re-check the active connection count while draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Shared shutdown state: a flag plus the means to wake everyone waiting on
+/// it, and a count of connections still being served.
+pub struct Shutdown {
+    /// Set once, the first time shutdown is triggered.
+    triggered: AtomicBool,
+    /// Wakes every accept loop blocked in [`Shutdown::wait`].
+    notify: Notify,
+    /// Number of connections currently being served; used to drain before
+    /// exiting.
+    active_connections: AtomicUsize,
+}
+
+/// Shared handle to the shutdown state, cloned into every task that needs to
+/// observe or trigger it.
+pub type SharedShutdown = Arc<Shutdown>;
+
+impl Shutdown {
+    /// Create a fresh, not-yet-triggered shared shutdown handle.
+    pub fn new_shared() -> SharedShutdown {
+        Arc::new(Self {
+            triggered: AtomicBool::new(false),
+            notify: Notify::new(),
+            active_connections: AtomicUsize::new(0),
+        })
+    }
+
+    /// Whether shutdown has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::Acquire)
+    }
+
+    /// Trigger shutdown, waking every accept loop blocked in
+    /// [`Shutdown::wait`]. Safe to call more than once (e.g. both a signal
+    /// and a `SHUTDOWN` command arriving around the same time).
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    /// Resolve once shutdown has been triggered, for use alongside
+    /// `listener.accept()` in a `select!`.
+    pub async fn wait(&self) {
+        if self.is_triggered() {
+            return;
+        }
+        self.notify.notified().await;
+    }
+
+    /// Wait for every in-flight connection to finish, up to
+    /// `DRAIN_TIMEOUT`, then return regardless so the process can exit even
+    /// if a client is left connected.
+    pub async fn drain(&self) {
+        let deadline = tokio_time::Instant::now() + DRAIN_TIMEOUT;
+        while self.active_connections.load(Ordering::Relaxed) > 0 && tokio_time::Instant::now() < deadline {
+            tokio_time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// RAII guard that counts a connection as active for as long as it's held,
+/// so [`Shutdown::drain`] knows when every connection has finished.
+pub struct ConnectionGuard(SharedShutdown);
+
+impl ConnectionGuard {
+    /// Mark a new connection as active.
+    pub fn new(shutdown: SharedShutdown) -> Self {
+        shutdown.active_connections.fetch_add(1, Ordering::Relaxed);
+        Self(shutdown)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Install `SIGTERM`/`SIGINT` handlers that trigger `shutdown` as soon as
+/// either arrives, so an operator (or a test harness) can stop the server
+/// cooperatively instead of having to send `SIGKILL`.
+pub fn install_signal_handlers(shutdown: SharedShutdown) {
+    tokio::spawn(async move {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = signal::ctrl_c() => {}
+        }
+        shutdown.trigger();
+    });
+}
@@ -0,0 +1,110 @@
+//! Server-wide statistics, exposed to operators and monitoring tooling via
+//! the `INFO` command.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Shared handle to the server's statistics, cloned into every connection task.
+pub type SharedStats = Arc<Stats>;
+
+/// Atomic counters tracked across every connection and rendered by `INFO`.
+#[derive(Default)]
+pub struct Stats {
+    /// Total number of client connections accepted since startup.
+    total_connections: AtomicU64,
+    /// Total number of commands processed since startup.
+    total_commands: AtomicU64,
+    /// Number of times each command (lowercased) has been processed.
+    command_counts: Mutex<HashMap<String, u64>>,
+    /// Number of `GET`s that found a live key.
+    keyspace_hits: AtomicU64,
+    /// Number of `GET`s that found no key, or one that had already expired.
+    keyspace_misses: AtomicU64,
+    /// Number of keys removed by passive or active expiry.
+    expired_keys: AtomicU64,
+}
+
+impl Stats {
+    /// Create a fresh, shared statistics handle with every counter at zero.
+    pub fn new_shared() -> SharedStats {
+        Arc::new(Self::default())
+    }
+
+    /// Record that a connection was accepted.
+    pub fn record_connection(&self) {
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that `command` (already lowercased) was processed.
+    pub fn record_command(&self, command: &str) {
+        self.total_commands.fetch_add(1, Ordering::Relaxed);
+        *self
+            .command_counts
+            .lock()
+            .unwrap()
+            .entry(command.to_owned())
+            .or_insert(0) += 1;
+    }
+
+    /// Record a `GET` that found a live key.
+    pub fn record_hit(&self) {
+        self.keyspace_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a `GET` that found no key, or an expired one.
+    pub fn record_miss(&self) {
+        self.keyspace_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a key removed by passive or active expiry.
+    pub fn record_expired(&self) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Render the `INFO` text format for `section` (every tracked section, when
+/// empty), matching the subset of Redis's `INFO` layout this server tracks:
+/// `# Server`, `# Clients`, `# Stats`, and `# Keyspace`.
+pub fn render(stats: &Stats, dbsize: usize, expires: usize, section: &str) -> String {
+    let section = section.to_lowercase();
+    let want = |name: &str| section.is_empty() || section == name;
+
+    let mut out = String::new();
+    if want("server") {
+        out.push_str("# Server\r\nredis_version:7.4.0\r\n\r\n");
+    }
+    if want("clients") {
+        out.push_str("# Clients\r\nconnected_clients:1\r\n\r\n");
+    }
+    if want("stats") {
+        out.push_str(&format!(
+            "# Stats\r\n\
+             total_connections_received:{}\r\n\
+             total_commands_processed:{}\r\n\
+             expired_keys:{}\r\n\
+             keyspace_hits:{}\r\n\
+             keyspace_misses:{}\r\n",
+            stats.total_connections.load(Ordering::Relaxed),
+            stats.total_commands.load(Ordering::Relaxed),
+            stats.expired_keys.load(Ordering::Relaxed),
+            stats.keyspace_hits.load(Ordering::Relaxed),
+            stats.keyspace_misses.load(Ordering::Relaxed),
+        ));
+        for (command, count) in &*stats.command_counts.lock().unwrap() {
+            out.push_str(&format!("cmdstat_{command}:calls={count}\r\n"));
+        }
+        out.push_str("\r\n");
+    }
+    if want("keyspace") {
+        out.push_str("# Keyspace\r\n");
+        if dbsize > 0 {
+            out.push_str(&format!("db0:keys={dbsize},expires={expires}\r\n"));
+        }
+    }
+    out
+}
@@ -0,0 +1,15 @@
+use redis::Commands;
+
+mod utils;
+
+#[test]
+fn test_ping_over_ipv6_loopback() {
+    let port = utils::find_free_tcp_port().to_string();
+    let _server = utils::start_server(&port, &["[::1]"]);
+
+    let client = redis::Client::open(format!("redis://[::1]:{port}/")).unwrap();
+    let mut con = client.get_connection().unwrap();
+
+    let ping_result: String = con.ping().unwrap();
+    assert_eq!(ping_result, "PONG");
+}
@@ -0,0 +1,18 @@
+use redis::Commands;
+
+mod utils;
+
+#[test]
+fn test_large_value_over_read_chunk_size() {
+    let mut test_server = utils::start_server_and_get_connection();
+    let con = &mut test_server.connection;
+
+    // Bigger than `READ_CHUNK_SIZE` (8 KiB), so the server must grow its read
+    // buffer across more than one socket read to see the whole frame.
+    let value = "x".repeat(64 * 1024);
+    let set_result: String = con.set("big", &value).unwrap();
+    assert_eq!(set_result, "OK");
+
+    let get_result: String = con.get("big").unwrap();
+    assert_eq!(get_result, value);
+}
@@ -0,0 +1,34 @@
+use redis::Commands;
+use std::thread;
+use std::time::Duration;
+
+mod utils;
+
+#[test]
+fn test_blpop_unblocks_on_push() {
+    let test_server = utils::start_server_and_get_connection();
+    let mut blocking_con = test_server.new_connection();
+    let mut pushing_con = test_server.new_connection();
+
+    let handle = thread::spawn(move || -> (String, String) {
+        blocking_con.blpop("blocking_list", 5.0).unwrap()
+    });
+
+    // Give the blocking client time to register interest before we push.
+    thread::sleep(Duration::from_millis(200));
+    let rpush_result: usize = pushing_con.rpush("blocking_list", "value").unwrap();
+    assert_eq!(rpush_result, 1);
+
+    let (key, value) = handle.join().unwrap();
+    assert_eq!(key, "blocking_list");
+    assert_eq!(value, "value");
+}
+
+#[test]
+fn test_blpop_times_out_when_empty() {
+    let mut test_server = utils::start_server_and_get_connection();
+    let con = &mut test_server.connection;
+
+    let result: Option<(String, String)> = con.blpop("never_pushed", 1.0).unwrap();
+    assert_eq!(result, None);
+}
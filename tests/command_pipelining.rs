@@ -0,0 +1,18 @@
+mod utils;
+
+#[test]
+fn test_pipelined_commands_reply_in_order() {
+    let mut test_server = utils::start_server_and_get_connection();
+    let con = &mut test_server.connection;
+
+    let (set_result, value, list_size): (String, Option<String>, usize) = redis::pipe()
+        .cmd("SET").arg("foo").arg("bar")
+        .cmd("GET").arg("foo")
+        .cmd("RPUSH").arg("list").arg(&["a", "b", "c"])
+        .query(con)
+        .unwrap();
+
+    assert_eq!(set_result, "OK");
+    assert_eq!(value, Some("bar".into()));
+    assert_eq!(list_size, 3);
+}
@@ -0,0 +1,57 @@
+use redis::Commands;
+use std::thread;
+use std::time::Duration;
+
+mod utils;
+
+#[test]
+fn test_publish_subscribe_fan_out() {
+    let test_server = utils::start_server_and_get_connection();
+    let mut subscriber_con = test_server.new_connection();
+    let mut publisher_con = test_server.new_connection();
+
+    let handle = thread::spawn(move || {
+        let mut pubsub = subscriber_con.as_pubsub();
+        pubsub.subscribe("news").unwrap();
+        let message: String = pubsub.get_message().unwrap().get_payload().unwrap();
+        message
+    });
+
+    // Give the subscriber time to register before we publish.
+    thread::sleep(Duration::from_millis(200));
+    let receiver_count: usize = publisher_con.publish("news", "hello").unwrap();
+    assert_eq!(receiver_count, 1);
+
+    let message = handle.join().unwrap();
+    assert_eq!(message, "hello");
+}
+
+#[test]
+fn test_psubscribe_matches_pattern() {
+    let test_server = utils::start_server_and_get_connection();
+    let mut subscriber_con = test_server.new_connection();
+    let mut publisher_con = test_server.new_connection();
+
+    let handle = thread::spawn(move || {
+        let mut pubsub = subscriber_con.as_pubsub();
+        pubsub.psubscribe("news.*").unwrap();
+        let message: String = pubsub.get_message().unwrap().get_payload().unwrap();
+        message
+    });
+
+    thread::sleep(Duration::from_millis(200));
+    let receiver_count: usize = publisher_con.publish("news.sports", "goal").unwrap();
+    assert_eq!(receiver_count, 1);
+
+    let message = handle.join().unwrap();
+    assert_eq!(message, "goal");
+}
+
+#[test]
+fn test_publish_with_no_subscribers_returns_zero() {
+    let mut test_server = utils::start_server_and_get_connection();
+    let con = &mut test_server.connection;
+
+    let receiver_count: usize = con.publish("nobody_listening", "hello").unwrap();
+    assert_eq!(receiver_count, 0);
+}
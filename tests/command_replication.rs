@@ -0,0 +1,62 @@
+use redis::Commands;
+use std::thread;
+use std::time::Duration;
+
+mod utils;
+
+#[test]
+fn test_write_on_master_appears_on_replica() {
+    let mut master = utils::start_server_and_get_connection();
+    let mut replica = utils::start_server_and_get_connection();
+
+    let replicaof_result: String = redis::cmd("REPLICAOF")
+        .arg(&["127.0.0.1", master.port()])
+        .query(&mut replica.connection)
+        .unwrap();
+    assert_eq!(replicaof_result, "OK");
+
+    // Give the replica a moment to complete the handshake before we write.
+    thread::sleep(Duration::from_millis(300));
+
+    let set_result: String = master.connection.set("foo", "bar").unwrap();
+    assert_eq!(set_result, "OK");
+
+    // Give the write a moment to propagate.
+    thread::sleep(Duration::from_millis(300));
+    let val: Option<String> = replica.connection.get("foo").unwrap();
+    assert_eq!(val, Some("bar".into()));
+}
+
+#[test]
+fn test_wait_reports_acked_replica() {
+    let mut master = utils::start_server_and_get_connection();
+    let mut replica = utils::start_server_and_get_connection();
+
+    let replicaof_result: String = redis::cmd("REPLICAOF")
+        .arg(&["127.0.0.1", master.port()])
+        .query(&mut replica.connection)
+        .unwrap();
+    assert_eq!(replicaof_result, "OK");
+
+    thread::sleep(Duration::from_millis(300));
+
+    let set_result: String = master.connection.set("foo", "bar").unwrap();
+    assert_eq!(set_result, "OK");
+
+    let acked: usize = redis::cmd("WAIT")
+        .arg(&[1, 1000])
+        .query(&mut master.connection)
+        .unwrap();
+    assert_eq!(acked, 1);
+}
+
+#[test]
+fn test_wait_returns_immediately_when_already_satisfied() {
+    let mut master = utils::start_server_and_get_connection();
+
+    let acked: usize = redis::cmd("WAIT")
+        .arg(&[0, 1000])
+        .query(&mut master.connection)
+        .unwrap();
+    assert_eq!(acked, 0);
+}
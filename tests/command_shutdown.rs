@@ -0,0 +1,36 @@
+use std::process::Command;
+use std::time::Duration;
+
+mod utils;
+
+#[test]
+fn test_sigterm_triggers_clean_exit() {
+    let port = utils::find_free_tcp_port().to_string();
+    let mut server = utils::start_server(&port, &[]);
+
+    let status = Command::new("kill")
+        .args(["-TERM", &server.pid().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let exit_status = server
+        .wait_for_exit(Duration::from_secs(5))
+        .expect("server did not exit after SIGTERM");
+    assert_eq!(exit_status.code(), Some(0));
+}
+
+#[test]
+fn test_shutdown_command_closes_connection() {
+    let mut test_server = utils::start_server_and_get_connection();
+    let con = &mut test_server.connection;
+
+    // Real Redis closes the connection instead of replying to `SHUTDOWN`.
+    let result: Result<(), redis::RedisError> = redis::cmd("SHUTDOWN").query(con);
+    assert!(result.is_err());
+
+    let exit_status = test_server
+        .wait_for_exit(Duration::from_secs(5))
+        .expect("server did not exit after SHUTDOWN");
+    assert_eq!(exit_status.code(), Some(0));
+}
@@ -1,9 +1,9 @@
 use std::{
     env,
     net::TcpListener,
-    process::{Child, Command},
+    process::{Child, Command, ExitStatus},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 pub struct ChildGuard(Child);
@@ -20,22 +20,51 @@ impl Drop for ChildGuard {
     }
 }
 
+impl ChildGuard {
+    // The OS process id, so a test can send it a signal directly instead of
+    // relying on `kill()` (SIGKILL).
+    #[allow(dead_code)]
+    pub fn pid(&self) -> u32 {
+        self.0.id()
+    }
+
+    // Poll for the process to exit on its own within `timeout`, returning
+    // its exit status if it did.
+    #[allow(dead_code)]
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.0.try_wait() {
+                return Some(status);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+}
+
 // Find a free port on the machine
 pub fn find_free_tcp_port() -> u16 {
     let socket = TcpListener::bind("127.0.0.1:0").unwrap();
     socket.local_addr().unwrap().port()
 }
 
-// Start the redis server on the specified port
-pub fn start_server(port: &str) -> ChildGuard {
+// Start the redis server on the specified port, listening only on each
+// given `--bind` spec (e.g. "[::1]" for the IPv6 loopback). Pass an empty
+// slice to get the server's own IPv4/IPv6 loopback defaults instead.
+pub fn start_server(port: &str, binds: &[&str]) -> ChildGuard {
     let binary_path = env::var("CARGO_MANIFEST_DIR").unwrap()
         + "/target/debug/"
         + env::var("CARGO_PKG_NAME").unwrap().as_ref();
     // println!("{binary_path}");
 
     // Adjust path if your binary is named differently
-    let child = Command::new(binary_path)
-        .arg(port)
+    let mut command = Command::new(binary_path);
+    command.arg(port);
+    for bind in binds {
+        command.arg("--bind").arg(bind);
+    }
+    let child = command
         // .stdout(std::process::Stdio::null())
         .spawn()
         .expect("Failed to spawn server");
@@ -49,16 +78,45 @@ pub fn start_server(port: &str) -> ChildGuard {
 #[allow(dead_code)]
 pub struct TestServer {
     server: ChildGuard,
+    port: String,
     pub connection: redis::Connection,
 }
 
+impl TestServer {
+    // Open another connection to the same server, so tests can exercise
+    // behaviour that depends on more than one client talking to it at once
+    // (e.g. a push on one connection unblocking a `BLPOP` on another).
+    #[allow(dead_code)]
+    pub fn new_connection(&self) -> redis::Connection {
+        redis::Client::open(format!("redis://127.0.0.1:{}/", self.port))
+            .unwrap()
+            .get_connection()
+            .unwrap()
+    }
+
+    // The port this server is listening on, so another server can be told
+    // to replicate from it.
+    #[allow(dead_code)]
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    // Poll for the server process to exit on its own within `timeout`, so a
+    // test can assert a clean shutdown instead of relying on `Drop`'s kill.
+    #[allow(dead_code)]
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> Option<std::process::ExitStatus> {
+        self.server.wait_for_exit(timeout)
+    }
+}
+
 pub fn start_server_and_get_connection() -> TestServer {
-    let port = &find_free_tcp_port().to_string();
-    let server = start_server(port);
+    let port = find_free_tcp_port().to_string();
+    let server = start_server(&port, &[]);
     let client = redis::Client::open(format!("redis://127.0.0.1:{port}/")).unwrap();
 
     TestServer {
         server,
         connection: client.get_connection().unwrap(),
+        port,
     }
 }